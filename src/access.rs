@@ -0,0 +1,72 @@
+use crate::store::Store;
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::time::SystemTime;
+
+/// When an entry was inserted and read, tracked with `StoreOptions::track_access`.
+/// Access metadata is kept in memory only and starts over when the store is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMeta {
+    /// When the key was first inserted through [`Store::insert`], or `None` if it was loaded from
+    /// disk or inserted through [`Store::map_mut`].
+    pub inserted_at: Option<SystemTime>,
+
+    /// When the entry was last read through [`Store::get_tracked`], if ever.
+    pub last_accessed_at: Option<SystemTime>,
+
+    /// How many times the entry was read through [`Store::get_tracked`].
+    pub access_count: u64,
+}
+
+impl EntryMeta {
+    pub(crate) fn inserted_now() -> Self {
+        Self {
+            inserted_at: Some(SystemTime::now()),
+            last_accessed_at: None,
+            access_count: 0,
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// The access metadata of `key`, or `None` if it has none, e.g. because `track_access` is
+    /// disabled or the entry was loaded from disk and never read with `get_tracked`.
+    pub fn entry_metadata<Q>(&self, key: &Q) -> Option<EntryMeta>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.access.get(key).copied()
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Like `get`, but records the read in the entry's [`EntryMeta`] when
+    /// `StoreOptions::track_access` is enabled, hence the mutable borrow.
+    pub fn get_tracked<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, value) = self.map.get_key_value(key)?;
+        if self.options.track_access {
+            let meta = self.access.entry(key.clone()).or_insert(EntryMeta {
+                inserted_at: None,
+                last_accessed_at: None,
+                access_count: 0,
+            });
+            meta.last_accessed_at = Some(SystemTime::now());
+            meta.access_count += 1;
+        }
+
+        Some(value)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::error::Result;
+use crate::persist::describe_key;
+use crate::store::{CompressionLevel, Store};
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+
+/// Size statistics for a store, produced by [`Store::analyze`].
+#[derive(Debug, Clone)]
+pub struct AnalysisReport<'a, K> {
+    /// The number of entries in the store.
+    pub entries: usize,
+
+    /// The size of the serialized payload before compression.
+    pub uncompressed_bytes: u64,
+
+    /// The size of the file `save` would write, including the header.
+    pub compressed_bytes: u64,
+
+    /// The entries with the largest serialized size, as their key and the combined size of the
+    /// serialized key and value, largest first.
+    pub largest: Vec<(&'a K, usize)>,
+
+    /// The number of entries by serialized size, bucketed by powers of two.
+    /// The entry at index `i` counts the entries whose combined key and value size is at least
+    /// `2^i` and less than `2^(i + 1)` bytes. Trailing empty buckets are omitted.
+    pub size_histogram: Vec<usize>,
+}
+
+/// What a save would write, produced by [`Store::save_dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavePreview {
+    /// The size of the file `save` would write, including the header.
+    pub bytes: u64,
+
+    /// The [`content_hash`](Store::content_hash) of the entries that would be written.
+    pub content_hash: blake3::Hash,
+}
+
+impl<K> AnalysisReport<'_, K> {
+    /// The average serialized size of an entry, or 0 for an empty store.
+    pub fn average_entry_bytes(&self) -> f64 {
+        match self.entries {
+            0 => 0.0,
+            entries => self.uncompressed_bytes as f64 / entries as f64,
+        }
+    }
+
+    /// The compressed size as a fraction of the uncompressed size.
+    pub fn compression_ratio(&self) -> f64 {
+        match self.uncompressed_bytes {
+            0 => 1.0,
+            uncompressed => self.compressed_bytes as f64 / uncompressed as f64,
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Measures how large the store is when saved and which entries contribute the most, listing
+    /// the `top` largest entries. Every entry is serialized individually and the whole store is
+    /// compressed once, so this costs about as much as a save.
+    pub fn analyze(&self, top: usize) -> Result<AnalysisReport<'_, K>> {
+        let mut largest = Vec::with_capacity(self.map.len());
+        let mut size_histogram = Vec::new();
+        for (key, value) in self.map.iter() {
+            let mut counter = ByteCounter(0);
+            rmp_serde::encode::write(&mut counter, key)?;
+            rmp_serde::encode::write(&mut counter, value)?;
+
+            let size = counter.0 as usize;
+            let bucket = size.max(1).ilog2() as usize;
+            if size_histogram.len() <= bucket {
+                size_histogram.resize(bucket + 1, 0);
+            }
+            size_histogram[bucket] += 1;
+            largest.push((key, size));
+        }
+        largest.sort_unstable_by_key(|&(_, size)| std::cmp::Reverse(size));
+        largest.truncate(top);
+
+        let mut counter = ByteCounter(0);
+        self.write_payload(&mut counter)?;
+
+        Ok(AnalysisReport {
+            entries: self.map.len(),
+            uncompressed_bytes: counter.0,
+            compressed_bytes: self.to_bytes()?.len() as u64,
+            largest,
+            size_histogram,
+        })
+    }
+
+    /// Estimates the compression ratio `level` would achieve, as the compressed size as a fraction
+    /// of the uncompressed size of the payload, from a sample of the entries. The sample takes
+    /// every n-th entry in the map's arbitrary iteration order, so that roughly `sample_fraction`
+    /// of them are compressed, and at least one is. This is far cheaper than saving the store at
+    /// each level to compare them, but small samples compress worse than the whole store would.
+    /// [`CompressionLevel::NONE`] is always `1.0`.
+    pub fn sample_compression(&self, level: CompressionLevel, sample_fraction: f64) -> Result<f64> {
+        if level.is_none() {
+            return Ok(1.0);
+        }
+
+        let step = match sample_fraction {
+            fraction if fraction >= 1.0 => 1,
+            fraction if fraction > 0.0 => (1.0 / fraction).round() as usize,
+            _ => usize::MAX,
+        };
+
+        let sample: Vec<_> = self.map.iter().step_by(step).collect();
+        let mut payload = Vec::new();
+        rmp::encode::write_map_len(&mut payload, sample.len() as u32)
+            .map_err(rmp_serde::encode::Error::from)?;
+        for (key, value) in sample {
+            rmp_serde::encode::write(&mut payload, key)?;
+            rmp_serde::encode::write(&mut payload, value)?;
+        }
+
+        let compressed = zstd::encode_all(payload.as_slice(), level.level())?;
+        Ok(compressed.len() as f64 / payload.len() as f64)
+    }
+
+    /// Trains a zstd dictionary of at most `max_size` bytes on the serialized entries, for
+    /// `StoreOptions::dictionary`. Training needs a good number of entries to learn from, and
+    /// fails with an I/O error when zstd can't build a dictionary from them.
+    pub fn train_dictionary(&self, max_size: usize) -> Result<Vec<u8>> {
+        let samples = self
+            .map
+            .iter()
+            .map(|entry| rmp_serde::encode::to_vec(&entry))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(zstd::dict::from_samples(&samples, max_size)?)
+    }
+
+    /// Runs a save through serialization and compression without writing anything, reporting the
+    /// size of the file it would write, e.g. to confirm a large save before running it. Use
+    /// [`to_bytes`](Self::to_bytes) to get the bytes themselves.
+    pub fn save_dry_run(&self) -> Result<SavePreview> {
+        let counter = self.write_with_level(ByteCounter(0), self.options.compression_level)?;
+        Ok(SavePreview {
+            bytes: counter.0,
+            content_hash: self.content_hash()?,
+        })
+    }
+
+    /// A one-line description of the store for logs, unlike its `Debug` output, which includes
+    /// every entry. It shows the path, the number of entries, the size of the serialized payload
+    /// before compression, and a few keys, with keys that aren't strings or integers shown as hex
+    /// and long keys truncated.
+    pub fn debug_summary(&self) -> String {
+        const SAMPLE_KEYS: usize = 5;
+        const MAX_KEY_CHARS: usize = 32;
+
+        let mut counter = ByteCounter(0);
+        let bytes = match self.write_payload(&mut counter) {
+            Ok(()) => counter.0.to_string(),
+            Err(_) => "unknown".to_string(),
+        };
+
+        let mut keys: Vec<String> = self
+            .map
+            .keys()
+            .take(SAMPLE_KEYS)
+            .map(|key| match rmp_serde::encode::to_vec(key) {
+                Ok(raw) => {
+                    let key = describe_key(&raw);
+                    match key.char_indices().nth(MAX_KEY_CHARS) {
+                        Some((end, _)) => format!("{}...", &key[..end]),
+                        None => key,
+                    }
+                }
+                Err(_) => "?".to_string(),
+            })
+            .collect();
+        if self.map.len() > SAMPLE_KEYS {
+            keys.push("...".to_string());
+        }
+
+        format!(
+            "Store {{ path: {:?}, entries: {}, bytes: {}, keys: [{}] }}",
+            self.options.path,
+            self.map.len(),
+            bytes,
+            keys.join(", ")
+        )
+    }
+}
+
+/// A writer that discards its input, keeping only a count of the bytes written.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::{Error, Result};
+use crate::persist::FileStamp;
+use crate::store::{Store, StoreOptions};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the backup generation `index` for the store at `path`,
+/// e.g. `data.ogma.bak.1` for the most recent backup of `data.ogma`.
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".bak.{index}"));
+    PathBuf::from(name)
+}
+
+/// Shifts existing backups of `path` up one generation, dropping the oldest beyond `count`,
+/// and makes the current file the most recent backup. The current file stays in place so that
+/// the rename in `save` still atomically replaces it.
+pub(crate) fn rotate(path: &Path, count: usize) -> io::Result<()> {
+    if count == 0 || !path.is_file() {
+        return Ok(());
+    }
+
+    for index in (1..count).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            replace(&from, &backup_path(path, index + 1))?;
+        }
+    }
+
+    let newest = backup_path(path, 1);
+    remove_if_exists(&newest)?;
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+
+    Ok(())
+}
+
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    // Renaming over an existing file fails on some platforms.
+    remove_if_exists(to)?;
+    fs::rename(from, to)
+}
+
+pub(crate) fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Opens the store, falling back to its backups if the primary file can't be read.
+    /// The primary file is tried first, followed by each of the `options.backups` generations from
+    /// newest to oldest. Returns the store along with the path it was actually loaded from.
+    /// Fails with the primary file's error only if no candidate could be read, and returns an empty
+    /// store if none of them exist.
+    pub fn open_resilient(options: StoreOptions) -> Result<(Self, PathBuf)> {
+        let candidates: Vec<PathBuf> = std::iter::once(options.path.clone())
+            .chain((1..=options.backups).map(|index| backup_path(&options.path, index)))
+            .filter(|path| path.is_file())
+            .collect();
+
+        let mut first_error = None;
+        for path in candidates {
+            match Self::open_file(&path, options.clone()) {
+                Ok(store) => {
+                    // Saving replaces the primary file, so that's the one to watch for changes.
+                    let primary = File::open(&options.path).and_then(|file| FileStamp::of(&file));
+                    store.disk_stamp.set(primary.ok());
+                    return Ok((store, path));
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => {
+                let path = options.path.clone();
+                Ok((Self::new(options), path))
+            }
+        }
+    }
+
+    /// Opens the backup generation `index` of the store, as numbered by [`backup_path`], for
+    /// point-in-time inspection or recovery. The backup is validated like any store file, and a
+    /// missing one fails with `Error::NoSuchBackup`. The store still saves to `options.path`, so
+    /// saving it restores the backup, and it needs saving until then.
+    pub fn open_backup(options: StoreOptions, index: usize) -> Result<Self> {
+        let path = backup_path(&options.path, index);
+        if !path.is_file() {
+            return Err(Error::NoSuchBackup(path));
+        }
+
+        let primary = FileStamp::of_path(&options.path)?;
+        let mut store = Self::open_file(&path, options)?;
+        store.disk_stamp.set(primary);
+        store.revision.bump();
+        Ok(store)
+    }
+}
@@ -0,0 +1,77 @@
+use crate::error::{Error, Result};
+use crate::store::{Store, StoreOptions};
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Encodes the store like [`to_bytes`](Self::to_bytes), as standard padded base64, e.g. to
+    /// embed a small store in an environment variable or a JSON string.
+    pub fn to_base64(&self) -> Result<String> {
+        Ok(encode(&self.to_bytes()?))
+    }
+
+    /// Decodes a store from a string produced by [`to_base64`](Self::to_base64).
+    /// Whitespace is ignored, so the string may be wrapped across lines.
+    pub fn from_base64(encoded: &str, options: StoreOptions) -> Result<Self> {
+        let bytes = decode(encoded).ok_or(Error::InvalidBase64)?;
+        Self::from_bytes(&bytes, options)
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes padded base64, returning `None` if it is malformed.
+fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = encoded
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if !digits.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() / 4 * 3);
+    let chunks = digits.len() / 4;
+    for (n, chunk) in digits.chunks(4).enumerate() {
+        // Padding may only end the last chunk, and never covers more than two digits.
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && n + 1 != chunks) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &digit in &chunk[..4 - padding] {
+            let value = ALPHABET.iter().position(|&b| b == digit)?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding;
+
+        bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(bytes)
+}
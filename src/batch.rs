@@ -0,0 +1,185 @@
+use crate::error::Result;
+use crate::persist::StagedSave;
+use crate::store::{DefaultHashBuilder, Store};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+
+mod sealed {
+    use super::{Result, StagedSave};
+    use std::fs::File;
+
+    /// The steps of a save, split up so that several stores can be saved together.
+    pub trait Saveable {
+        fn lock_for_write(&self) -> Result<Option<File>>;
+        fn stage(&self) -> Result<StagedSave>;
+        fn check_unchanged(&self) -> Result<()>;
+        fn commit(&self, staged: StagedSave) -> Result<()>;
+    }
+}
+
+/// A store that can be saved together with others by [`save_all`].
+/// This is implemented for every [`Store`] that can be saved.
+pub trait SaveableStore: sealed::Saveable {}
+
+impl<K, V, S> sealed::Saveable for Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn lock_for_write(&self) -> Result<Option<File>> {
+        Store::lock_for_write(self)
+    }
+
+    fn stage(&self) -> Result<StagedSave> {
+        Store::stage(self, self.options.compression_level)
+    }
+
+    fn check_unchanged(&self) -> Result<()> {
+        Store::check_unchanged(self)
+    }
+
+    fn commit(&self, staged: StagedSave) -> Result<()> {
+        Store::commit(self, staged)
+    }
+}
+
+impl<K, V, S> SaveableStore for Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+}
+
+/// Saves several stores so that either all of them are replaced or none are.
+/// The locks of stores with `StoreOptions::access_mode` set are all taken before anything is
+/// written. Every store is then written to its temporary file, and only once all of them are
+/// written, and none were modified by someone else where `check_before_overwrite` is set, are
+/// they renamed into place. If anything fails before then, every temporary file is removed and no
+/// store is changed.
+///
+/// The renames themselves happen one after another, so a crash or a failed rename partway
+/// through can still leave some stores replaced and others not. This narrows the window for
+/// related stores to disagree rather than closing it entirely.
+pub fn save_all(stores: &[&dyn SaveableStore]) -> Result<()> {
+    let _locks = stores
+        .iter()
+        .map(|store| store.lock_for_write())
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut staged = Vec::with_capacity(stores.len());
+    let result = stores.iter().try_for_each(|store| {
+        staged.push(store.stage()?);
+        Ok(())
+    });
+    let result = result.and_then(|()| stores.iter().try_for_each(|store| store.check_unchanged()));
+
+    if let Err(e) = result {
+        for staged in &staged {
+            let _ = std::fs::remove_file(&staged.temp_path);
+        }
+        return Err(e);
+    }
+
+    stores
+        .iter()
+        .zip(staged)
+        .try_for_each(|(store, staged)| store.commit(staged))
+}
+
+/// Changes staged by [`Store::transaction`], applied to the store only if the transaction succeeds.
+/// Reads see the staged changes on top of the store's entries.
+#[derive(Debug)]
+pub struct Txn<'a, K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    store: &'a Store<K, V, S>,
+    ops: Vec<(K, Option<V>)>,
+}
+
+impl<K, V, S> Txn<'_, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Stages inserting `value` under `key`.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.ops.push((key, Some(value)));
+    }
+
+    /// Stages removing `key`.
+    pub fn remove(&mut self, key: K) {
+        self.ops.push((key, None));
+    }
+
+    /// The value of `key` as it will be once the transaction is applied.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.ops.iter().rev().find(|(k, _)| k.borrow() == key) {
+            Some((_, value)) => value.as_ref(),
+            None => self.store.get(key),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Runs `f` against a [`Txn`] and applies the inserts and removals it staged, in order, only
+    /// if it returns `Ok`. If `f` fails or panics, the store is left exactly as it was.
+    /// Staged changes go through [`insert`](Self::insert) and [`remove`](Self::remove), so they
+    /// are reported and tracked like any other.
+    pub fn transaction<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Txn<'_, K, V, S>) -> Result<()>,
+    {
+        let mut txn = Txn {
+            store: self,
+            ops: Vec::new(),
+        };
+        f(&mut txn)?;
+
+        for (key, value) in txn.ops {
+            match value {
+                Some(value) => self.insert(key, value),
+                None => self.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Like [`transaction`](Self::transaction), then saves the store if the transaction was
+    /// applied. If saving fails, the changes stay applied in memory.
+    pub fn commit_and_save<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Txn<'_, K, V, S>) -> Result<()>,
+    {
+        self.transaction(f)?;
+        self.save()
+    }
+}
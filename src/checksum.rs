@@ -0,0 +1,76 @@
+use crate::error::Result;
+use crate::hash::Blake3Writer;
+use crate::store::Store;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+
+/// A Blake3 hash of a serialized value, written to disk as a 32-byte binary string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Digest([u8; 32]);
+
+impl Digest {
+    pub(crate) fn of<V: Serialize>(value: &V) -> Result<Self> {
+        let mut writer = Blake3Writer::new(io::sink());
+        rmp_serde::encode::write(&mut writer, value)?;
+        Ok(Self(*writer.finalize().1.as_bytes()))
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct DigestVisitor;
+
+        impl Visitor<'_> for DigestVisitor {
+            type Value = Digest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a 32-byte digest")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> std::result::Result<Digest, E> {
+                v.try_into()
+                    .map(Digest)
+                    .map_err(|_| E::invalid_length(v.len(), &self))
+            }
+        }
+
+        deserializer.deserialize_bytes(DigestVisitor)
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    V: Serialize,
+    S: BuildHasher + Default,
+{
+    /// Checks the value of `key` against the checksum that was saved alongside it, returning
+    /// `false` if the value no longer matches or the entry has no checksum. Checksums are read when
+    /// the store is opened, so an entry changed since then, whether on disk or in memory, fails the
+    /// check. Only files saved with `StoreOptions::entry_checksums` enabled have checksums, and
+    /// entries changed by incremental saves have none until the next full save.
+    pub fn verify_entry<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(value) = self.map.get(key) else {
+            return Ok(false);
+        };
+
+        match self.checksums.get(key) {
+            Some(expected) => Ok(*expected == Digest::of(value)?),
+            None => Ok(false),
+        }
+    }
+}
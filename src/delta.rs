@@ -0,0 +1,557 @@
+use crate::backup;
+use crate::error::{Error, Result};
+use crate::metrics::Operation;
+use crate::persist::FileStamp;
+use crate::store::{CompressionLevel, Store};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::UNIX_EPOCH;
+
+const MAGIC_ID: &[u8] = b"OGMD";
+const VERSION: u16 = 1;
+
+/// The size of the delta file header: magic, version, and the stamp of the base it applies to.
+const HEADER_LEN: u64 = 4 + 2 + 8 + 8;
+
+/// Returns the path of the delta file for the store at `path`, e.g. `data.ogma.delta`.
+pub fn delta_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".delta");
+    PathBuf::from(name)
+}
+
+/// The keys changed since the last save, for `StoreOptions::incremental`.
+/// It is reset by `save`, which only borrows the store, hence the mutex.
+#[derive(Debug)]
+pub(crate) struct ChangeSet<K>(Mutex<Changes<K>>);
+
+#[derive(Debug, Clone)]
+pub(crate) struct Changes<K> {
+    keys: HashSet<K>,
+    cleared: bool,
+    sequence: bool,
+
+    /// Whether the delta file no longer applies to the store, so the next save has to be full.
+    rewrite: bool,
+}
+
+impl<K> Default for Changes<K> {
+    fn default() -> Self {
+        Self {
+            keys: HashSet::new(),
+            cleared: false,
+            sequence: false,
+            rewrite: false,
+        }
+    }
+}
+
+impl<K> ChangeSet<K> {
+    pub(crate) fn new() -> Self {
+        Self(Mutex::new(Changes::default()))
+    }
+
+    fn get_mut(&mut self) -> &mut Changes<K> {
+        self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes every recorded change, leaving the set empty.
+    pub(crate) fn take(&self) -> Changes<K> {
+        mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Records that every key was removed, which supersedes the changes recorded so far.
+    pub(crate) fn clear(&mut self) {
+        let changes = self.get_mut();
+        changes.keys.clear();
+        changes.cleared = true;
+    }
+
+    /// Records that the id sequence advanced.
+    pub(crate) fn record_sequence(&mut self) {
+        self.get_mut().sequence = true;
+    }
+
+    /// Records that the delta file can't be appended to, e.g. because the values changed type.
+    pub(crate) fn rewrite(&mut self) {
+        self.get_mut().rewrite = true;
+    }
+
+    fn needs_rewrite(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rewrite
+    }
+}
+
+impl<K: Eq + Hash> ChangeSet<K> {
+    pub(crate) fn record(&mut self, key: K) {
+        self.get_mut().keys.insert(key);
+    }
+
+    /// Puts back changes taken by a save that failed, so that the next save writes them.
+    fn restore(&self, changes: Changes<K>) {
+        let mut current = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if changes.cleared {
+            // The later changes still apply on top of the earlier clear.
+            current.cleared = true;
+        }
+        current.sequence |= changes.sequence;
+        current.rewrite |= changes.rewrite;
+        current.keys.extend(changes.keys);
+    }
+}
+
+/// Where the delta file of the snapshot with the given stamp ends after its last complete layer,
+/// so that appending a layer doesn't have to scan the whole file for it.
+/// It is updated by `save_incremental`, which only borrows the store, hence the mutex.
+#[derive(Debug, Default)]
+pub(crate) struct DeltaEnd(Mutex<Option<(FileStamp, u64)>>);
+
+impl DeltaEnd {
+    /// The end of the delta file of `base`, if it's known.
+    fn get(&self, base: Option<FileStamp>) -> Option<u64> {
+        match *self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some((stamp, end)) if Some(stamp) == base => Some(end),
+            _ => None,
+        }
+    }
+
+    fn set(&self, base: Option<FileStamp>, end: u64) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = base.map(|base| (base, end));
+    }
+}
+
+impl Clone for DeltaEnd {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner),
+        ))
+    }
+}
+
+impl<K: Clone> Clone for ChangeSet<K> {
+    fn clone(&self) -> Self {
+        let changes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        Self(Mutex::new(changes.clone()))
+    }
+}
+
+/// The changes written by one call to `save_incremental`, borrowing from the store.
+#[derive(Serialize)]
+struct LayerRef<'a, K: Eq + Hash, V> {
+    cleared: bool,
+    upserts: HashMap<&'a K, &'a V>,
+    removed: Vec<&'a K>,
+    tombstones: HashMap<&'a K, u64>,
+
+    /// Written only when the id sequence advanced or expiries follow, so that layers without
+    /// either keep the original four elements. It is nil when only the expiries are written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<Option<u64>>,
+
+    /// The expiry times of upserted keys that have one, written only when there are any.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    expiries: HashMap<&'a K, u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>")]
+struct Layer<K, V> {
+    cleared: bool,
+    upserts: HashMap<K, V>,
+    removed: Vec<K>,
+    tombstones: HashMap<K, u64>,
+    #[serde(default)]
+    sequence: Option<u64>,
+    #[serde(default)]
+    expiries: HashMap<K, u64>,
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Records `key` as changed for the next [`save_incremental`](Self::save_incremental), for
+    /// changes the store can't see, such as through a value's interior mutability.
+    /// Does nothing unless `StoreOptions::incremental` is enabled.
+    pub fn mark_changed(&mut self, key: K) {
+        if self.options.incremental {
+            self.changes.record(key);
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Appends only the entries changed since the last save to the delta file next to the store
+    /// (see [`delta_path`]), which is much cheaper than rewriting a large, mostly unchanged store.
+    /// Changes are only tracked with `StoreOptions::incremental` enabled.
+    ///
+    /// [`open`](Self::open) applies the delta file on top of the base snapshot. A full
+    /// [`save`](Self::save) or [`compact`](Self::compact) writes a new snapshot and removes it.
+    /// If there is no base snapshot yet, the file on disk isn't the one this store last opened
+    /// or saved, the store changed in a way a layer can't hold, such as through
+    /// [`map_values`](Self::map_values) or [`map_mut`](Self::map_mut), or the delta file has
+    /// outgrown `StoreOptions::auto_compact_ratio`, this does a full save instead.
+    pub fn save_incremental(&self) -> Result<()> {
+        let lock = self.lock_for_write()?;
+        let timer = self
+            .metrics
+            .time(self.options.collect_metrics, Operation::Save);
+        let base = FileStamp::of_path(&self.options.path)?;
+        if base.is_none()
+            || base != self.disk_stamp.get()
+            || self.changes.needs_rewrite()
+            || self.needs_compaction()?
+        {
+            // A full save takes the lock and times itself.
+            drop((lock, timer));
+            return self.save();
+        }
+
+        let changes = self.changes.take();
+        if changes.keys.is_empty() && !changes.cleared && !changes.sequence {
+            if self.revision.is_saved() {
+                return Ok(());
+            }
+
+            // The store changed in a way no layer records, so only a full save can write it.
+            drop((lock, timer));
+            return self.save();
+        }
+
+        let result = self.append_layer(&changes, base);
+        match result {
+            Ok(()) => {
+                self.revision.mark_saved();
+                self.flush_on_drop();
+            }
+            Err(_) => self.changes.restore(changes),
+        }
+
+        result
+    }
+
+    /// Writes a new snapshot that includes every delta layer and removes the delta file.
+    /// This is the same as a full [`save`](Self::save), and is worth doing once the delta file
+    /// has grown large enough to slow down opening the store, or once
+    /// [`disk_size`](Self::disk_size) has grown well past the size of a fresh save. A store with
+    /// no file yet is simply saved for the first time.
+    pub fn compact(&self) -> Result<()> {
+        self.save()
+    }
+
+    /// Whether the delta file has grown past `StoreOptions::auto_compact_ratio` of the snapshot it
+    /// applies to.
+    pub(crate) fn needs_compaction(&self) -> Result<bool> {
+        let (Some(ratio), Some(base)) = (self.options.auto_compact_ratio, self.disk_stamp.get())
+        else {
+            return Ok(false);
+        };
+
+        let len = match std::fs::metadata(delta_path(&self.options.path)) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(len as f64 > base.len as f64 * ratio as f64 && exists(&self.options.path, base)?)
+    }
+
+    fn append_layer(&self, changes: &Changes<K>, base: Option<FileStamp>) -> Result<()> {
+        let mut layer = LayerRef {
+            cleared: changes.cleared,
+            upserts: HashMap::new(),
+            removed: Vec::new(),
+            tombstones: HashMap::new(),
+            sequence: None,
+            expiries: HashMap::new(),
+        };
+
+        for key in &changes.keys {
+            match self.map.get_key_value(key) {
+                Some((key, value)) => {
+                    layer.upserts.insert(key, value);
+                    if let Some(&expiry) = self.expiries.get(key) {
+                        layer.expiries.insert(key, expiry);
+                    }
+                }
+                None => {
+                    layer.removed.push(key);
+                    if let Some(&deleted) = self.tombstones.get(key) {
+                        layer.tombstones.insert(key, deleted);
+                    }
+                }
+            }
+        }
+
+        if changes.sequence || !layer.expiries.is_empty() {
+            layer.sequence = Some(changes.sequence.then_some(self.sequence));
+        }
+
+        // Layers are always zstd frames, so `CompressionLevel::NONE` compresses as little as it can.
+        let level = self
+            .options
+            .compression_level
+            .level()
+            .max(CompressionLevel::ZSTD_MIN);
+        let encoded = rmp_serde::encode::to_vec(&layer)?;
+        // The frame's checksum catches a layer that was torn by a crash but still has its length.
+        let mut enc = zstd::Encoder::new(Vec::new(), level)?;
+        enc.include_checksum(true)?;
+        enc.write_all(&encoded)?;
+        let frame = enc.finish()?;
+
+        let path = delta_path(&self.options.path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        // The end is only scanned for if this store didn't open the delta file or append to it.
+        let header = encode_header(base);
+        let end = match read_header(&mut file)? == Some(header) {
+            true => match self.delta_end.get(base) {
+                Some(end) if end <= file.metadata()?.len() => end,
+                _ => valid_len(&mut file)?,
+            },
+            false => {
+                // The delta file belongs to an older snapshot, so none of it applies any more.
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&header)?;
+                HEADER_LEN
+            }
+        };
+
+        // Drop whatever is left of a layer that was only partially written.
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+        file.write_u32::<LittleEndian>(frame.len() as u32)?;
+        file.write_all(&frame)?;
+        file.sync_all()?;
+        self.delta_end.set(base, end + 4 + frame.len() as u64);
+
+        Ok(())
+    }
+
+    /// Applies the delta file for the snapshot at `path`, if it has one, on top of the store
+    /// read from that snapshot.
+    pub(crate) fn apply_delta(&mut self, path: &Path, base: FileStamp) -> Result<()> {
+        let limit = self.options.max_decompressed_bytes;
+        let end = for_each_layer(path, base, limit, |layer: Layer<K, V>| {
+            if layer.cleared {
+                self.map.clear();
+                self.expiries.clear();
+            }
+            for key in layer.removed {
+                self.map.remove(&key);
+                self.expiries.remove(&key);
+            }
+            for (key, value) in layer.upserts {
+                self.tombstones.remove(&key);
+                self.checksums.remove(&key);
+                self.expiries.remove(&key);
+                self.map.insert(key, value);
+            }
+            self.tombstones.extend(layer.tombstones);
+            self.expiries.extend(layer.expiries);
+            if let Some(sequence) = layer.sequence {
+                self.sequence = sequence;
+            }
+        })?;
+
+        if let Some(end) = end {
+            self.delta_end.set(Some(base), end);
+        }
+        Ok(())
+    }
+}
+
+/// Applies the delta file for the snapshot at `path`, if it has one, to the keys read from that
+/// snapshot without decoding any values. The layers may decompress to at most `limit` bytes.
+pub(crate) fn apply_to_keys<K>(
+    keys: &mut HashSet<K>,
+    path: &Path,
+    base: FileStamp,
+    limit: Option<u64>,
+) -> Result<()>
+where
+    K: Eq + Hash + for<'de> Deserialize<'de>,
+{
+    for_each_layer(path, base, limit, |layer: Layer<K, IgnoredAny>| {
+        if layer.cleared {
+            keys.clear();
+        }
+        for key in layer.removed {
+            keys.remove(&key);
+        }
+        keys.extend(layer.upserts.into_keys());
+    })?;
+
+    Ok(())
+}
+
+/// Whether the snapshot at `path` has a delta file that applies to it.
+pub(crate) fn exists(path: &Path, base: FileStamp) -> Result<bool> {
+    match File::open(delta_path(path)) {
+        Ok(mut file) => Ok(read_header(&mut file)? == Some(encode_header(Some(base)))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Calls `f` on every layer of the delta file for the snapshot at `path`, returning where the
+/// last complete layer ends, or `None` if the snapshot has no delta file. Decompressing more than
+/// `limit` bytes of layers in total fails with `Error::DecompressionLimitExceeded`.
+fn for_each_layer<K, V, F>(
+    path: &Path,
+    base: FileStamp,
+    mut limit: Option<u64>,
+    mut f: F,
+) -> Result<Option<u64>>
+where
+    K: Eq + Hash + for<'de> Deserialize<'de>,
+    V: for<'de> Deserialize<'de>,
+    F: FnMut(Layer<K, V>),
+{
+    let mut file = match File::open(delta_path(path)) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    if read_header(&mut file)? != Some(encode_header(Some(base))) {
+        return Ok(None);
+    }
+
+    let mut end = HEADER_LEN;
+    while let Some(frame) = read_frame(&mut file)? {
+        let decoded = match decode_frame(&frame, &mut limit) {
+            Ok(decoded) => decoded,
+            Err(Error::Io(_)) if file.fill_buf()?.is_empty() => break,
+            Err(e) => return Err(e),
+        };
+        f(rmp_serde::decode::from_slice(&decoded)?);
+        end += 4 + frame.len() as u64;
+    }
+
+    Ok(Some(end))
+}
+
+/// Removes the delta file for the store at `path` after a full save has superseded it.
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    backup::remove_if_exists(&delta_path(path))
+}
+
+fn encode_header(base: Option<FileStamp>) -> [u8; HEADER_LEN as usize] {
+    let (len, modified) = base.map_or((0, 0), |stamp| {
+        let modified = stamp
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        (stamp.len, modified)
+    });
+
+    let mut header = [0u8; HEADER_LEN as usize];
+    header[..4].copy_from_slice(MAGIC_ID);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6..14].copy_from_slice(&len.to_le_bytes());
+    header[14..].copy_from_slice(&modified.to_le_bytes());
+    header
+}
+
+/// Reads the header of a delta file, or `None` if the file is too short to have one.
+fn read_header<R: Read>(reader: &mut R) -> Result<Option<[u8; HEADER_LEN as usize]>> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) if &header[..4] != MAGIC_ID => Err(Error::InvalidFile),
+        Ok(()) => Ok(Some(header)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads the next layer's compressed frame, or `None` at the end of the file or at a layer that
+/// was cut short, e.g. by a crash while it was being appended.
+fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32::<LittleEndian>() {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut frame = Vec::new();
+    reader.take(len as u64).read_to_end(&mut frame)?;
+    Ok((frame.len() == len as usize).then_some(frame))
+}
+
+/// Decompresses a layer's frame, failing with `Error::DecompressionLimitExceeded` if it holds more
+/// than the `remaining` bytes, which are then used up by it.
+fn decode_frame(frame: &[u8], remaining: &mut Option<u64>) -> Result<Vec<u8>> {
+    let mut decoder = zstd::Decoder::new(frame)?;
+    let mut decoded = Vec::new();
+    let Some(remaining) = remaining else {
+        decoder.read_to_end(&mut decoded)?;
+        return Ok(decoded);
+    };
+
+    decoder.take(*remaining + 1).read_to_end(&mut decoded)?;
+    *remaining = remaining
+        .checked_sub(decoded.len() as u64)
+        .ok_or(Error::DecompressionLimitExceeded)?;
+    Ok(decoded)
+}
+
+/// The length of the delta file up to the end of its last complete layer, for a delta file that
+/// the store didn't open or append to itself.
+/// The last layer is only complete if it also decodes, since it's the one a crash could have torn.
+fn valid_len(file: &mut File) -> Result<u64> {
+    let total = file.metadata()?.len();
+    let mut end = HEADER_LEN;
+    let mut last = None;
+    loop {
+        file.seek(SeekFrom::Start(end))?;
+        let len = match file.read_u32::<LittleEndian>() {
+            Ok(len) => len as u64,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if end + 4 + len > total {
+            break;
+        }
+        last = Some(end);
+        end += 4 + len;
+    }
+
+    if let Some(start) = last {
+        file.seek(SeekFrom::Start(start))?;
+        // The layer is decompressed into nothing, so even a huge one takes no memory.
+        if let Some(frame) = read_frame(file)?
+            && zstd::stream::copy_decode(&frame[..], io::sink()).is_err()
+        {
+            return Ok(start);
+        }
+    }
+
+    Ok(end)
+}
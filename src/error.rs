@@ -1,21 +1,129 @@
 use thiserror::Error;
 
-pub type Result<T> = std::result::Result<T, crate::error::Error>;
+pub type Result<T> = core::result::Result<T, crate::error::Error>;
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[cfg(feature = "std")]
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
     #[error("file is not a valid store file or is corrupted")]
     InvalidFile,
 
-    #[error("file format is version {actual}, but this library only supports version {expected}")]
-    WrongVersion { expected: u16, actual: u16 },
+    #[error("the string is not valid base64")]
+    InvalidBase64,
 
+    #[error(
+        "file format is version {file}, which is newer than version {supported} supported by this library"
+    )]
+    FileTooNew { file: u16, supported: u16 },
+
+    #[error(
+        "file format is version {file}, which is older than version {supported} and must be migrated"
+    )]
+    FileTooOld { file: u16, supported: u16 },
+
+    #[error("file uses features unsupported by this library (flags {0:#06x})")]
+    UnsupportedFlags(u16),
+
+    #[error("the file on disk was modified since the store was opened or last saved")]
+    ConcurrentModification,
+
+    #[error("the payload decompresses to more than the configured maximum size")]
+    DecompressionLimitExceeded,
+
+    #[error("cannot atomically move a store file to a different filesystem")]
+    CrossFilesystem,
+
+    #[error("file was saved with type tag {found}, but the store expects {expected}")]
+    TypeMismatch { expected: u32, found: u32 },
+
+    #[error("file was compressed with a dictionary, but none was provided")]
+    DictionaryRequired,
+
+    #[error("file was compressed with a different dictionary than the one provided")]
+    DictionaryMismatch,
+
+    #[error("the store is locked by another process")]
+    Locked,
+
+    #[cfg(feature = "std")]
+    #[error("file is corrupted: its checksum is {actual}, but {expected} was recorded")]
+    ChecksumMismatch {
+        expected: blake3::Hash,
+        actual: blake3::Hash,
+    },
+
+    #[cfg(feature = "std")]
+    #[error("{} exists but is not a file", .0.display())]
+    NotAFile(std::path::PathBuf),
+
+    #[cfg(feature = "std")]
+    #[error("the store has no path to save to or open from")]
+    InvalidPath,
+
+    #[cfg(feature = "std")]
+    #[error("backup {} does not exist", .0.display())]
+    NoSuchBackup(std::path::PathBuf),
+
+    #[error("the entries of a sorted run must be in strictly increasing key order")]
+    UnsortedRun,
+
+    #[cfg(feature = "std")]
+    #[error("the store failed validation: {0}")]
+    Validation(String),
+
+    #[cfg(feature = "std")]
+    #[error("key {0} is already present")]
+    DuplicateKey(String),
+
+    #[cfg(feature = "std")]
+    #[error("section {0} holds a store with different key or value types")]
+    SectionType(String),
+
+    #[cfg(feature = "std")]
     #[error(transparent)]
     Decode(#[from] rmp_serde::decode::Error),
 
+    #[cfg(feature = "std")]
+    #[error("failed to decode the value for key {key}: {source}")]
+    ValueDecode {
+        key: String,
+        #[source]
+        source: rmp_serde::decode::Error,
+    },
+
+    #[cfg(feature = "std")]
     #[error(transparent)]
     Encode(#[from] rmp_serde::encode::Error),
+
+    #[cfg(any(feature = "query", feature = "json"))]
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Something [`Store::open_recover`](crate::Store::open_recover) had to work around while
+/// salvaging a damaged store file. Offsets are into the decompressed payload.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum RecoveryWarning {
+    /// Opening the file normally failed with this error, so its entries were salvaged one by one.
+    OpenFailed(Error),
+
+    /// The payload stopped decompressing after this many bytes, or its checksum was cut short
+    /// right after them, usually because the file is truncated. Only the entries within those
+    /// bytes can be recovered.
+    Truncated { decompressed: u64 },
+
+    /// The entry in this byte range was well-formed but its key or value didn't decode, so it was
+    /// skipped. Its key is rendered for diagnostics even when it didn't decode.
+    SkippedEntry {
+        range: std::ops::Range<u64>,
+        key: String,
+    },
+
+    /// The payload could no longer be followed from this offset, so the remaining entries were
+    /// dropped.
+    Unreadable { offset: u64, dropped: usize },
 }
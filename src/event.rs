@@ -0,0 +1,85 @@
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A change made to a [`Store`](crate::Store), delivered to the sender registered with
+/// [`Store::set_event_sender`](crate::Store::set_event_sender).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreEvent<K> {
+    /// A key that was not previously present was inserted.
+    Inserted(K),
+
+    /// The value of an existing key was replaced.
+    Updated(K),
+
+    /// A key and its value were removed.
+    Removed(K),
+
+    /// Every entry was removed.
+    Cleared,
+}
+
+/// A change made to a [`Store`](crate::Store), with the values involved, passed to the hook
+/// registered with [`Store::set_change_hook`](crate::Store::set_change_hook).
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChangeEvent<'a, K, V> {
+    /// `key` was inserted with the value `new`, replacing `old` if it was already present.
+    Inserted {
+        key: &'a K,
+        old: Option<&'a V>,
+        new: &'a V,
+    },
+
+    /// `key` and its value were removed.
+    Removed { key: &'a K, value: &'a V },
+
+    /// Every entry was removed.
+    Cleared,
+}
+
+/// A callback registered with [`Store::set_change_hook`](crate::Store::set_change_hook).
+#[cfg(feature = "std")]
+pub type ChangeHook<K, V> = Box<dyn FnMut(&ChangeEvent<'_, K, V>) + Send>;
+
+/// The change hook of a store, shared with its clones.
+#[cfg(feature = "std")]
+pub(crate) struct Hook<K, V>(Option<Arc<Mutex<ChangeHook<K, V>>>>);
+
+#[cfg(feature = "std")]
+impl<K, V> Hook<K, V> {
+    pub(crate) fn set(&mut self, hook: Option<ChangeHook<K, V>>) {
+        self.0 = hook.map(|hook| Arc::new(Mutex::new(hook)));
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn call(&self, event: &ChangeEvent<'_, K, V>) {
+        if let Some(hook) = &self.0 {
+            (hook.lock().unwrap_or_else(PoisonError::into_inner))(event);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Clone for Hook<K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Default for Hook<K, V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> fmt::Debug for Hook<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Hook").field(&self.is_set()).finish()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::store::Store;
+use crate::tombstone::now;
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Returns the value of `key`, or `None` if it isn't present or has expired.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.is_expired(key) {
+            true => None,
+            false => self.map.get(key),
+        }
+    }
+
+    /// Whether `key` is present and hasn't expired.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        !self.is_expired(key) && self.map.contains_key(key)
+    }
+
+    /// When the entry of `key` expires, or `None` if it never does.
+    pub fn expires_at<Q>(&self, key: &Q) -> Option<SystemTime>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let &millis = self.expiries.get(key)?;
+        Some(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    pub(crate) fn is_expired<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        !self.expiries.is_empty() && self.expiries.get(key).is_some_and(|&at| at <= now())
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Inserts a key-value pair like [`insert`](Self::insert), but expires it once `ttl` has
+    /// passed, after which [`get`](Self::get) and [`contains_key`](Self::contains_key) treat it as
+    /// absent. Inserting the key again without a TTL makes it permanent.
+    ///
+    /// Expired entries stay in the map, and are still seen by iteration, `len`, and lookups
+    /// through the underlying map, until [`purge_expired`](Self::purge_expired) removes them, or
+    /// until a method that would hand out their values mutably, such as
+    /// [`get_mut`](Self::get_mut) or [`entry`](Self::entry), removes them on access.
+    /// The expiry is saved as an absolute wall-clock time so that it survives reopening the
+    /// store, which means that setting the system clock back keeps entries alive for longer, and
+    /// setting it forward expires them early.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let old = self.insert(key.clone(), value);
+        self.expiries.insert(key, now().saturating_add(ttl));
+        old
+    }
+
+    /// Removes the entry of `key` with [`remove`](Self::remove) if it has expired, before its value
+    /// is handed out mutably.
+    pub(crate) fn remove_expired<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_expired(key) {
+            self.remove(key);
+        }
+    }
+
+    /// Removes every expired entry with [`remove`](Self::remove), returning how many were removed.
+    pub fn purge_expired(&mut self) -> usize {
+        let now = now();
+        let expired: Vec<K> = self
+            .expiries
+            .iter()
+            .filter(|&(_, &at)| at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired
+            .iter()
+            .filter(|key| self.remove(*key).is_some())
+            .count()
+    }
+}
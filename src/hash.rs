@@ -1,5 +1,5 @@
 use blake3::{Hash, Hasher};
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
 
 pub struct Blake3Reader<R: Read> {
     inner: R,
@@ -14,8 +14,14 @@ impl<R: Read> Blake3Reader<R> {
         }
     }
 
-    pub fn finalize(self) -> (R, Hash) {
-        (self.inner, self.hasher.finalize())
+    /// The hash of everything read so far.
+    pub fn digest(&self) -> Hash {
+        self.hasher.finalize()
+    }
+
+    /// The underlying reader. Reading from it directly bypasses the hash.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
     }
 }
 
@@ -31,6 +37,20 @@ impl<R: Read> Read for Blake3Reader<R> {
     }
 }
 
+impl<R: BufRead> BufRead for Blake3Reader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The bytes being consumed are still buffered, so this never reads.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hasher.update(&buf[..amt]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
 pub struct Blake3Writer<W: Write> {
     inner: W,
     hasher: Hasher,
@@ -44,6 +64,13 @@ impl<W: Write> Blake3Writer<W> {
         }
     }
 
+    pub fn new_keyed(inner: W, key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new_keyed(key),
+        }
+    }
+
     pub fn finalize(self) -> (W, Hash) {
         (self.inner, self.hasher.finalize())
     }
@@ -51,8 +78,9 @@ impl<W: Write> Blake3Writer<W> {
 
 impl<W: Write> Write for Blake3Writer<W> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.hasher.update(buf);
-        self.inner.write(buf)
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
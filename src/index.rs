@@ -0,0 +1,284 @@
+use crate::store::Store;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: 'static,
+    S: BuildHasher + Default,
+{
+    /// Registers a secondary index named `name`, which maps every value to the index key that
+    /// `extract` returns, so that [`query_index`](Self::query_index) can find the entries with a
+    /// given index key without scanning the store. Registering a name again replaces its index.
+    ///
+    /// The index is built from the current entries and kept up to date by every method of the
+    /// store, including values modified through the references handed out by
+    /// [`get_mut`](Self::get_mut), [`entry`](Self::entry), [`iter_mut`](Self::iter_mut), and
+    /// [`map_mut`](Self::map_mut). Those entries are left out of the index until the next change
+    /// to the store puts them back under their new index keys, and until then
+    /// [`query_index`](Self::query_index) checks their values directly. Indexes are never saved,
+    /// so a store opened from disk starts without any.
+    pub fn add_index<I, F>(&mut self, name: &str, extract: F)
+    where
+        I: Eq + Hash + Clone + Send + Sync + 'static,
+        F: Fn(&V) -> I + Clone + Send + Sync + 'static,
+    {
+        self.refresh_indexes();
+        let mut index = Index {
+            extract,
+            entries: HashMap::new(),
+            _value: PhantomData,
+        };
+        for (key, value) in &self.map {
+            index.insert(key, value);
+        }
+        self.indexes
+            .indexes
+            .insert(name.to_owned(), Box::new(index));
+    }
+
+    /// Removes the index named `name`, returning whether there was one.
+    pub fn remove_index(&mut self, name: &str) -> bool {
+        self.indexes.indexes.remove(name).is_some()
+    }
+
+    /// Returns the entries that the index `name` maps to `index_key`, in no particular order and
+    /// skipping expired entries. Nothing is returned if there is no such index, or if it was
+    /// registered with a different index key type.
+    pub fn query_index<I>(&self, name: &str, index_key: &I) -> impl Iterator<Item = (&K, &V)>
+    where
+        I: Eq + Hash + 'static,
+    {
+        let index = self.indexes.indexes.get(name);
+        let indexed = index
+            .and_then(|index| index.entries().downcast_ref::<HashMap<I, HashSet<K>>>())
+            .and_then(|entries| entries.get(index_key))
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.map.get_key_value(key));
+
+        // Entries whose values were handed out mutably since the last change aren't indexed.
+        let stale: Box<dyn Iterator<Item = (&K, &V)>> = match (index, self.indexes.all_stale) {
+            (None, _) => Box::new(std::iter::empty()),
+            (Some(_), true) => Box::new(self.map.iter()),
+            (Some(_), false) => Box::new(
+                self.indexes
+                    .stale
+                    .iter()
+                    .filter_map(|key| self.map.get_key_value(key)),
+            ),
+        };
+        let stale = stale
+            .filter(move |(_, value)| index.is_some_and(|index| index.matches(value, index_key)));
+
+        indexed
+            .chain(stale)
+            .filter(|(key, _)| !self.is_expired(*key))
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Rebuilds every index from the current entries. The store keeps its indexes up to date on
+    /// its own, so this is only needed to release the memory of index keys left over by a bug in
+    /// an extractor that isn't deterministic.
+    pub fn rebuild_indexes(&mut self) {
+        self.indexes.clear();
+        for (key, value) in &self.map {
+            self.indexes.insert(key, value);
+        }
+    }
+
+    /// Puts the entries whose values were handed out mutably back into the indexes, under the
+    /// index keys of their current values.
+    pub(crate) fn refresh_indexes(&mut self) {
+        if self.indexes.all_stale {
+            self.rebuild_indexes();
+            return;
+        }
+
+        for key in std::mem::take(&mut self.indexes.stale) {
+            if let Some(value) = self.map.get(&key) {
+                self.indexes.insert(&key, value);
+            }
+        }
+    }
+}
+
+/// The secondary indexes registered with [`Store::add_index`], by name.
+pub(crate) struct Indexes<K, V> {
+    indexes: HashMap<String, Box<dyn AnyIndex<K, V>>>,
+
+    /// Keys whose values were handed out mutably, and were taken out of the indexes since they
+    /// may no longer match their index keys.
+    stale: HashSet<K>,
+
+    /// Whether every value may have been modified, as if every key were in `stale`.
+    all_stale: bool,
+}
+
+impl<K, V> Indexes<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            indexes: HashMap::new(),
+            stale: HashSet::new(),
+            all_stale: false,
+        }
+    }
+
+    /// Records that `key` now holds `new` instead of `old`.
+    pub(crate) fn replace(&mut self, key: &K, old: Option<&V>, new: &V) {
+        for index in self.indexes.values_mut() {
+            if let Some(old) = old {
+                index.remove(key, old);
+            }
+            index.insert(key, new);
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: &K, value: &V) {
+        for index in self.indexes.values_mut() {
+            index.insert(key, value);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &K, value: &V) {
+        for index in self.indexes.values_mut() {
+            index.remove(key, value);
+        }
+    }
+
+    /// Takes `key`, which holds `value`, out of the indexes before its value is handed out mutably.
+    pub(crate) fn invalidate(&mut self, key: &K, value: &V)
+    where
+        K: Eq + Hash + Clone,
+    {
+        if self.indexes.is_empty() || self.all_stale {
+            return;
+        }
+        if self.stale.insert(key.clone()) {
+            self.remove(key, value);
+        }
+    }
+
+    /// Records that any value may be modified, e.g. before handing out the whole map.
+    pub(crate) fn invalidate_all(&mut self) {
+        if !self.indexes.is_empty() {
+            self.clear();
+            self.all_stale = true;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for index in self.indexes.values_mut() {
+            index.clear();
+        }
+        self.stale.clear();
+        self.all_stale = false;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+}
+
+impl<K: Clone, V> Clone for Indexes<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            indexes: self
+                .indexes
+                .iter()
+                .map(|(name, index)| (name.clone(), index.clone_box()))
+                .collect(),
+            stale: self.stale.clone(),
+            all_stale: self.all_stale,
+        }
+    }
+}
+
+impl<K, V> Default for Indexes<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> fmt::Debug for Indexes<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.indexes.keys()).finish()
+    }
+}
+
+/// A secondary index with its index key type erased, so that indexes of different types can be
+/// registered with the same store.
+trait AnyIndex<K, V>: Send + Sync {
+    fn insert(&mut self, key: &K, value: &V);
+    fn remove(&mut self, key: &K, value: &V);
+    fn clear(&mut self);
+
+    /// The index's `HashMap<I, HashSet<K>>`, to be downcast by lookups.
+    fn entries(&self) -> &dyn Any;
+
+    /// Whether `value` has the index key `index_key`, which is an `I`.
+    fn matches(&self, value: &V, index_key: &dyn Any) -> bool;
+
+    fn clone_box(&self) -> Box<dyn AnyIndex<K, V>>;
+}
+
+/// Maps every index key that `extract` returns to the keys of the entries it was returned for.
+struct Index<K, V, I, F> {
+    extract: F,
+    entries: HashMap<I, HashSet<K>>,
+    _value: PhantomData<fn(&V)>,
+}
+
+impl<K, V, I, F> AnyIndex<K, V> for Index<K, V, I, F>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: 'static,
+    I: Eq + Hash + Clone + Send + Sync + 'static,
+    F: Fn(&V) -> I + Clone + Send + Sync + 'static,
+{
+    fn insert(&mut self, key: &K, value: &V) {
+        let keys = self.entries.entry((self.extract)(value)).or_default();
+        keys.insert(key.clone());
+    }
+
+    fn remove(&mut self, key: &K, value: &V) {
+        let index_key = (self.extract)(value);
+        if let Some(keys) = self.entries.get_mut(&index_key) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&index_key);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn entries(&self) -> &dyn Any {
+        &self.entries
+    }
+
+    fn matches(&self, value: &V, index_key: &dyn Any) -> bool {
+        index_key
+            .downcast_ref::<I>()
+            .is_some_and(|index_key| (self.extract)(value) == *index_key)
+    }
+
+    fn clone_box(&self) -> Box<dyn AnyIndex<K, V>> {
+        Box::new(Self {
+            extract: self.extract.clone(),
+            entries: self.entries.clone(),
+            _value: PhantomData,
+        })
+    }
+}
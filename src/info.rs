@@ -0,0 +1,59 @@
+use crate::error::{Error, Result};
+use crate::persist;
+use crate::store::CompressionLevel;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A summary of a store file read from its header, without decoding any entries.
+/// Fields that can't be determined cheaply, or at all for a version this library doesn't
+/// support, are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreInfo {
+    /// The version of the file format.
+    pub version: u16,
+
+    /// Whether the payload is compressed.
+    pub compressed: Option<bool>,
+
+    /// The level the payload was compressed with, recorded since version 6 of the format.
+    /// Uncompressed payloads report `None`.
+    pub compression_level: Option<CompressionLevel>,
+
+    /// The number of entries, recorded since version 6 of the format. Stores with pending
+    /// incremental saves report `None`.
+    pub entry_count: Option<usize>,
+
+    /// The size of the file on disk.
+    pub size: u64,
+
+    /// When the file was last saved.
+    pub modified: Option<SystemTime>,
+}
+
+/// Reads the summary of the store file at `path`.
+/// Fails with `Error::InvalidFile` if the file isn't a store.
+pub fn inspect(path: impl AsRef<Path>) -> Result<StoreInfo> {
+    persist::inspect(path.as_ref())
+}
+
+/// Lists every store file directly inside `dir` along with its summary, sorted by path.
+/// Files that aren't stores, including ones that can't be read, are skipped.
+pub fn scan_dir(dir: impl AsRef<Path>) -> Result<Vec<(PathBuf, StoreInfo)>> {
+    let mut stores = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        match inspect(&path) {
+            Ok(info) => stores.push((path, info)),
+            Err(Error::InvalidFile | Error::Io(_)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    stores.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(stores)
+}
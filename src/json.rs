@@ -0,0 +1,60 @@
+use crate::error::Result;
+use crate::store::{Store, StoreOptions};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hash};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::io::{BufReader, Read, Write};
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Renders the entries as a JSON array of `[key, value]` pairs, one per line, e.g. to inspect
+    /// a store or keep a backup that diffs well. Keys of any type are supported since they aren't
+    /// used as object keys, and the lines are sorted so that equal stores render identically.
+    /// Nothing but the entries is included.
+    pub fn to_json(&self) -> Result<String> {
+        let mut lines = self
+            .map
+            .iter()
+            .map(|entry| serde_json::to_string(&entry))
+            .collect::<core::result::Result<Vec<_>, _>>()?;
+        lines.sort_unstable();
+
+        let mut json = String::from("[");
+        for (i, line) in lines.iter().enumerate() {
+            json.push_str(if i == 0 { "\n  " } else { ",\n  " });
+            json.push_str(line);
+        }
+        json.push_str("\n]\n");
+        Ok(json)
+    }
+
+    /// Parses JSON rendered by [`to_json`](Self::to_json), inserting every entry into a new store
+    /// with `options` as if by [`from_iter_with_options`](Self::from_iter_with_options).
+    pub fn from_json(json: &str, options: StoreOptions) -> Result<Self> {
+        let entries: Vec<(K, V)> = serde_json::from_str(json)?;
+        Ok(Self::from_iter_with_options(entries, options))
+    }
+
+    /// Writes the entries to `writer` in the format of [`to_json`](Self::to_json).
+    #[cfg(feature = "std")]
+    pub fn export_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.to_json()?.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a store exported by [`export_json`](Self::export_json), like
+    /// [`from_json`](Self::from_json). The file at `options.path` isn't read, so saving the result
+    /// replaces it wholesale.
+    #[cfg(feature = "std")]
+    pub fn import_json<R: Read>(reader: R, options: StoreOptions) -> Result<Self> {
+        let entries: Vec<(K, V)> = serde_json::from_reader(BufReader::new(reader))?;
+        Ok(Self::from_iter_with_options(entries, options))
+    }
+}
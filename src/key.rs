@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A `u64` key tagged with a marker type so that keys meant for different stores can't be mixed up.
+/// `TypedKey<UserTag>` and `TypedKey<SessionTag>` are distinct types, but both serialize as a plain
+/// `u64`, so wrapping an existing store's keys does not change its on-disk format.
+///
+/// The tag is never constructed, so an empty enum such as `enum UserTag {}` works well.
+pub struct TypedKey<Tag> {
+    id: u64,
+    _tag: PhantomData<fn() -> Tag>,
+}
+
+impl<Tag> TypedKey<Tag> {
+    pub const fn new(id: u64) -> Self {
+        Self {
+            id,
+            _tag: PhantomData,
+        }
+    }
+
+    /// Returns the underlying `u64`.
+    pub const fn get(self) -> u64 {
+        self.id
+    }
+}
+
+// These are implemented by hand because deriving them would require `Tag` to implement them too.
+
+impl<Tag> Clone for TypedKey<Tag> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Tag> Copy for TypedKey<Tag> {}
+
+impl<Tag> PartialEq for TypedKey<Tag> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<Tag> Eq for TypedKey<Tag> {}
+
+impl<Tag> PartialOrd for TypedKey<Tag> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Tag> Ord for TypedKey<Tag> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl<Tag> Hash for TypedKey<Tag> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<Tag> fmt::Debug for TypedKey<Tag> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.id, f)
+    }
+}
+
+impl<Tag> fmt::Display for TypedKey<Tag> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.id, f)
+    }
+}
+
+impl<Tag> From<u64> for TypedKey<Tag> {
+    fn from(id: u64) -> Self {
+        Self::new(id)
+    }
+}
+
+impl<Tag> From<TypedKey<Tag>> for u64 {
+    fn from(key: TypedKey<Tag>) -> Self {
+        key.id
+    }
+}
+
+impl<Tag> Serialize for TypedKey<Tag> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
+
+impl<'de, Tag> Deserialize<'de> for TypedKey<Tag> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// A compact binary encoding for a key type.
+/// Keys wrapped in [`CompactKey`] are written to disk as the bytes produced by [`encode`](Self::encode)
+/// instead of their regular serde representation, which avoids the per-field overhead that
+/// struct keys otherwise carry in MessagePack.
+pub trait KeyCodec: Sized {
+    /// Appends the encoded form of `self` to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a key previously produced by [`encode`](Self::encode), or `None` if the bytes are invalid.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// A key that serializes through its [`KeyCodec`] implementation as a single byte string.
+/// Apart from serialization it behaves exactly like the wrapped key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CompactKey<K>(pub K);
+
+impl<K> CompactKey<K> {
+    pub fn into_inner(self) -> K {
+        self.0
+    }
+}
+
+impl<K> From<K> for CompactKey<K> {
+    fn from(key: K) -> Self {
+        Self(key)
+    }
+}
+
+impl<K: KeyCodec> Serialize for CompactKey<K> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut buf = Vec::new();
+        self.0.encode(&mut buf);
+        serializer.serialize_bytes(&buf)
+    }
+}
+
+impl<'de, K: KeyCodec> Deserialize<'de> for CompactKey<K> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CompactKeyVisitor<K>(PhantomData<K>);
+
+        impl<'de, K: KeyCodec> Visitor<'de> for CompactKeyVisitor<K> {
+            type Value = CompactKey<K>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an encoded key")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                K::decode(v)
+                    .map(CompactKey)
+                    .ok_or_else(|| E::invalid_value(Unexpected::Bytes(v), &self))
+            }
+
+            // Formats without a native byte string type represent bytes as a sequence.
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        deserializer.deserialize_bytes(CompactKeyVisitor(PhantomData))
+    }
+}
@@ -1,16 +1,81 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod access;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod backup;
+#[cfg(feature = "std")]
+mod base64;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+mod checksum;
+#[cfg(feature = "std")]
+pub mod delta;
 pub mod error;
+pub mod event;
+#[cfg(feature = "std")]
+mod expiry;
+#[cfg(feature = "std")]
 mod hash;
+#[cfg(feature = "std")]
+mod index;
+#[cfg(feature = "std")]
+pub mod info;
+#[cfg(feature = "json")]
+mod json;
+pub mod key;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+pub mod maintenance;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod multi;
+#[cfg(feature = "std")]
+mod order;
+#[cfg(feature = "std")]
+pub mod ordered;
+#[cfg(feature = "std")]
+mod persist;
+#[cfg(feature = "query")]
+pub mod query;
+#[cfg(feature = "std")]
+mod run;
+#[cfg(feature = "std")]
+pub mod shared;
 pub mod store;
+#[cfg(feature = "std")]
+mod tombstone;
+#[cfg(feature = "std")]
+pub mod watch;
 
+#[cfg(feature = "std")]
+pub use event::ChangeHook;
+pub use event::{ChangeEvent, StoreEvent};
+/// The map a [`Store`] dereferences to, whether or not the `std` feature is enabled.
+pub use hashbrown::HashMap;
+pub use key::{CompactKey, KeyCodec, TypedKey};
+#[cfg(feature = "std")]
+pub use multi::MultiStore;
+#[cfg(feature = "std")]
+pub use ordered::OrderedStore;
+#[cfg(feature = "std")]
+pub use shared::{CowSharedStore, SharedStore};
 pub use store::Store;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
-    use crate::store::StoreOptions;
+    use crate::store::{AccessMode, CompressionLevel, OnDuplicate, StoreOptions};
     use serde::{Deserialize, Serialize};
 
-    #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
     pub struct Address {
         pub street: String,
         pub apt: Option<String>,
@@ -19,7 +84,7 @@ mod tests {
         pub zip: String,
     }
 
-    #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
     pub struct Person {
         pub first_name: String,
         pub middle_initial: Option<char>,
@@ -62,6 +127,2068 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_multithreaded_compression() -> error::Result<()> {
+        let path = temp_path("multithreaded");
+        let options = StoreOptions::new(&path).with_compression_threads(4);
+        let mut store: Store<u64, Person> = Store::new(options);
+        for i in 0..100 {
+            store.insert(i, get_person());
+        }
+
+        store.save()?;
+        let store: Store<u64, Person> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(store.len(), 100);
+        assert_eq!(store.get(&42), Some(&get_person()));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_key_format() -> error::Result<()> {
+        enum PersonTag {}
+
+        let path = temp_path("typed-key");
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::new(&path));
+        store.insert(5, get_person());
+        store.save()?;
+
+        let store: Store<TypedKey<PersonTag>, Person> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(store.get(&TypedKey::new(5)), Some(&get_person()));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_events() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut store: Store<u64, Person> =
+            Store::new(StoreOptions::default()).with_event_sender(tx);
+
+        store.insert(5, get_person());
+        store.insert(5, get_person());
+        store.remove(&5);
+        store.remove(&5);
+        store.clear();
+
+        let events: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                StoreEvent::Inserted(5),
+                StoreEvent::Updated(5),
+                StoreEvent::Removed(5),
+                StoreEvent::Cleared,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_hook() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut store: Store<u64, String> = Store::new(StoreOptions::default());
+        let hook_log = std::sync::Arc::clone(&log);
+        store.set_change_hook(Some(Box::new(
+            move |event: &ChangeEvent<'_, u64, String>| {
+                hook_log.lock().unwrap().push(match event {
+                    ChangeEvent::Inserted { key, old, new } => {
+                        format!("insert {key} {old:?} {new}")
+                    }
+                    ChangeEvent::Removed { key, value } => format!("remove {key} {value}"),
+                    ChangeEvent::Cleared => "clear".to_string(),
+                });
+            },
+        )));
+
+        store.insert(1, "a".into());
+        store.insert(1, "b".into());
+        store.insert(2, "c".into());
+        store.remove(&1);
+        store.remove(&1);
+        store.retain(|_, value| value != "c");
+        store.insert(3, "d".into());
+        store.clear();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "insert 1 None a",
+                "insert 1 Some(\"a\") b",
+                "insert 2 None c",
+                "remove 1 b",
+                "remove 2 c",
+                "insert 3 None d",
+                "clear",
+            ]
+        );
+
+        // Clones share the hook.
+        store.clone().insert(4, "e".into());
+        assert_eq!(log.lock().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_load_keys() -> error::Result<()> {
+        let path = temp_path("load-keys");
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::new(&path));
+        store.insert(1, get_person());
+        store.insert(2, get_person());
+        store.save()?;
+
+        let keys = Store::<u64, Person>::load_keys(&StoreOptions::new(&path))?;
+        assert_eq!(keys, [1, 2].into_iter().collect());
+        assert_eq!(
+            Store::<u64, Person>::count_entries(&StoreOptions::new(&path))?,
+            2
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_mismatch() -> error::Result<()> {
+        let path = temp_path("version-mismatch");
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::new(&path));
+        store.insert(5, get_person());
+        store.save()?;
+
+        let mut bytes = std::fs::read(&path)?;
+        bytes[4..6].copy_from_slice(&u16::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes)?;
+        let result = Store::<u64, Person>::open(StoreOptions::new(&path));
+        assert!(matches!(result, Err(error::Error::FileTooNew { .. })));
+
+        // A newer file is decoded as the current version, and saving it writes that version.
+        let options = StoreOptions::new(&path).with_force_open(true);
+        let forced = Store::<u64, Person>::open(options)?;
+        assert_eq!(forced.get(&5), Some(&get_person()));
+        forced.save()?;
+        assert!(!Store::<u64, Person>::needs_migration(&path)?);
+        assert_eq!(
+            Store::<u64, Person>::open(StoreOptions::new(&path))?.len(),
+            1
+        );
+
+        let mut bytes = std::fs::read(&path)?;
+        bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+        std::fs::write(&path, &bytes)?;
+        let result = Store::<u64, Person>::open(StoreOptions::new(&path));
+        assert!(matches!(result, Err(error::Error::FileTooOld { .. })));
+
+        let options = StoreOptions::new(&path).with_force_open(true);
+        assert_eq!(Store::<u64, Person>::open(options)?.len(), 1);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_older_versions() -> error::Result<()> {
+        let path = temp_path("older-versions");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+
+        // Version 5 is the current format without the entry count and compression level.
+        let mut v5 = store.to_bytes()?;
+        v5.drain(8..20);
+        v5.truncate(v5.len() - 32);
+        v5[4..6].copy_from_slice(&5u16.to_le_bytes());
+        let trailer = blake3::hash(&v5[6..]);
+
+        // Version 4 is version 5 without the checksum trailer.
+        let mut v4 = v5.clone();
+        v4[4..6].copy_from_slice(&4u16.to_le_bytes());
+        v5.extend(trailer.as_bytes());
+
+        // Version 3 has no flags and always compresses the payload.
+        let mut v3 = b"OGMA".to_vec();
+        v3.extend(3u16.to_le_bytes());
+        let payload = rmp_serde::to_vec(&(std::collections::HashMap::from([(2u64, 2u64)]),))?;
+        v3.extend(zstd::encode_all(payload.as_slice(), 3)?);
+
+        for (bytes, key) in [(v5, 1), (v4, 1), (v3, 2)] {
+            std::fs::write(&path, bytes)?;
+            assert!(Store::<u64, u64>::needs_migration(&path)?);
+            let info = info::inspect(&path)?;
+            assert_eq!(info.compressed, Some(true));
+            assert_eq!(info.compression_level, None);
+            assert_eq!(info.entry_count, None);
+            let store: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+            assert_eq!(store.get(&key), Some(&key));
+            assert!(store.needs_save());
+
+            store.save()?;
+            assert!(!Store::<u64, u64>::needs_migration(&path)?);
+        }
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_store_save() -> error::Result<()> {
+        let path = temp_path("shared-save");
+        let shared = SharedStore::new(Store::<u64, Person>::new(StoreOptions::new(&path)));
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::spawn(move || shared.insert(i, get_person()))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(shared.get(&3).is_some());
+        assert!(shared.remove(&3).is_some());
+
+        shared.save()?;
+        let store: Store<u64, Person> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(store.len(), 3);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_store_cursor() {
+        let shared = SharedStore::new(Store::<u64, u64>::from_iter_with_options(
+            (0..10).map(|i| (i, i)),
+            StoreOptions::default(),
+        ));
+        let mut cursor = shared.cursor();
+        let page: Vec<u64> = cursor
+            .next_page(3)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(page, [0, 1, 2]);
+
+        shared.write().remove(&3);
+        shared.write().insert(4, 40);
+        shared.write().insert(10, 10);
+        assert_eq!(cursor.next_page(2), [(4, 40), (5, 5)]);
+        assert_eq!(cursor.next_page(10).len(), 4);
+        assert!(cursor.next_page(10).is_empty());
+    }
+
+    #[test]
+    fn test_cow_shared_store() -> error::Result<()> {
+        let path = temp_path("cow_shared");
+        let shared = CowSharedStore::new(Store::<u64, u64>::new(StoreOptions::new(&path)));
+        shared.update(|store| store.insert(1, 1));
+
+        let snapshot = shared.load();
+        shared.update(|store| store.insert(2, 2));
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(shared.load().len(), 2);
+
+        shared.save()?;
+        assert_eq!(Store::<u64, u64>::open(StoreOptions::new(&path))?.len(), 2);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_key() -> error::Result<()> {
+        #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+        struct Point {
+            x: u32,
+            y: u32,
+        }
+
+        impl KeyCodec for Point {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.x.to_le_bytes());
+                buf.extend_from_slice(&self.y.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Option<Self> {
+                let (x, y) = bytes.split_at_checked(4)?;
+                Some(Point {
+                    x: u32::from_le_bytes(x.try_into().ok()?),
+                    y: u32::from_le_bytes(y.try_into().ok()?),
+                })
+            }
+        }
+
+        let path = temp_path("compact-key");
+        let mut store: Store<CompactKey<Point>, Person> = Store::new(StoreOptions::new(&path));
+        store.insert(CompactKey(Point { x: 1, y: 2 }), get_person());
+        store.save()?;
+
+        let store: Store<CompactKey<Point>, Person> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(
+            store.get(&CompactKey(Point { x: 1, y: 2 })),
+            Some(&get_person())
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_round_trip() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.insert(5, get_person());
+
+        let bytes = store.to_bytes()?;
+        let store: Store<u64, Person> = Store::from_bytes(&bytes, StoreOptions::default())?;
+        assert_eq!(store.get(&5), Some(&get_person()));
+
+        let mut written = Vec::new();
+        store.write_to(&mut written)?;
+        assert_eq!(written, bytes);
+        let store: Store<u64, Person> =
+            Store::read_from(written.as_slice(), StoreOptions::default())?;
+        assert_eq!(store.get(&5), Some(&get_person()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.insert(5, get_person());
+        store.set_metadata(Some(b"schema v2".to_vec()));
+
+        let bytes = store.to_bytes()?;
+        let store: Store<u64, Person> = Store::from_bytes(&bytes, StoreOptions::default())?;
+        assert_eq!(store.metadata(), Some(&b"schema v2"[..]));
+        assert_eq!(store.get(&5), Some(&get_person()));
+
+        let path = temp_path("metadata-incremental");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, Person> = Store::new(options.clone());
+        store.insert(5, get_person());
+        store.save()?;
+        store.set_metadata(Some(b"schema v3".to_vec()));
+        store.save_incremental()?;
+        assert!(!store.needs_save());
+        let reopened: Store<u64, Person> = Store::open(options)?;
+        assert_eq!(reopened.metadata(), Some(&b"schema v3"[..]));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_reader_stops_at_end() -> error::Result<()> {
+        let mut first: Store<u64, u64> = Store::new(StoreOptions::default());
+        first.insert(1, 1);
+        let mut second: Store<u64, u64> =
+            Store::new(StoreOptions::default().with_min_compress_bytes(64));
+        second.insert(2, 2);
+
+        let mut bytes = first.to_bytes()?;
+        bytes.extend(second.to_bytes()?);
+        bytes.extend(b"trailer");
+
+        let mut reader = std::io::Cursor::new(bytes.as_slice());
+        let a: Store<u64, u64> = Store::from_reader(&mut reader, StoreOptions::default())?;
+        let b: Store<u64, u64> = Store::from_reader(&mut reader, StoreOptions::default())?;
+        assert_eq!((a.get(&1), b.get(&2)), (Some(&1), Some(&2)));
+        assert_eq!(&bytes[reader.position() as usize..], b"trailer");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_decompressed_bytes() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        for i in 0..100 {
+            store.insert(i, get_person());
+        }
+
+        let bytes = store.to_bytes()?;
+        let options = StoreOptions::default().with_max_decompressed_bytes(Some(1024));
+        let result = Store::<u64, Person>::from_bytes(&bytes, options);
+        assert!(matches!(
+            result,
+            Err(error::Error::DecompressionLimitExceeded)
+        ));
+
+        // The snapshot is small, but a layer of its delta file decompresses to far more.
+        let path = temp_path("max-decompressed-delta");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, String> = Store::new(options.clone());
+        store.insert(1, "small".into());
+        store.save()?;
+        store.insert(2, "x".repeat(1 << 20));
+        store.save_incremental()?;
+        assert!(std::fs::metadata(delta::delta_path(&path))?.len() < 1024);
+
+        let limited = options.with_max_decompressed_bytes(Some(1024));
+        let limit_exceeded = |result: error::Result<usize>| {
+            matches!(result, Err(error::Error::DecompressionLimitExceeded))
+        };
+        assert!(limit_exceeded(
+            Store::<u64, String>::open(limited.clone()).map(|store| store.len())
+        ));
+        assert!(limit_exceeded(
+            Store::<u64, String>::load_keys(&limited).map(|keys| keys.len())
+        ));
+        assert!(limit_exceeded(Store::<u64, String>::count_entries(
+            &limited
+        )));
+        assert_eq!(
+            Store::<u64, String>::open(StoreOptions::new(&path))?.len(),
+            2
+        );
+
+        std::fs::remove_file(delta::delta_path(&path))?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_map_length() -> error::Result<()> {
+        // A header claiming far more entries than the file holds.
+        let mut bytes = b"OGMA".to_vec();
+        bytes.extend(6u16.to_le_bytes());
+        bytes.extend([0; 14]);
+        bytes.extend([0x91, 0xdf, 0xff, 0xff, 0xff, 0xff]);
+
+        let result = Store::<u64, u64>::from_bytes(&bytes, StoreOptions::default());
+        assert!(result.is_err());
+
+        let path = temp_path("untrusted-map-length");
+        std::fs::write(&path, &bytes)?;
+        assert!(Store::<u64, u64>::open(StoreOptions::new(&path)).is_err());
+        assert!(Store::<u64, u64>::load_keys(&StoreOptions::new(&path)).is_err());
+        assert!(Store::<String, u64>::count_key_prefix(&StoreOptions::new(&path), "a").is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash() -> error::Result<()> {
+        let mut a: Store<u64, Person> = Store::new(StoreOptions::default());
+        let mut b: Store<u64, Person> = Store::new(StoreOptions::default());
+        for i in 0..50 {
+            a.insert(i, get_person());
+            b.insert(49 - i, get_person());
+        }
+
+        assert_eq!(a.content_hash()?, b.content_hash()?);
+        assert_eq!(
+            a.content_hash_keyed([1; 32])?,
+            b.content_hash_keyed([1; 32])?
+        );
+        assert_ne!(
+            a.content_hash_keyed([1; 32])?,
+            a.content_hash_keyed([2; 32])?
+        );
+
+        b.remove(&0);
+        assert_ne!(a.content_hash()?, b.content_hash()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_truncated() -> error::Result<()> {
+        let path = temp_path("repair");
+        let mut store: Store<u64, u64> =
+            Store::new(StoreOptions::new(&path).with_compression_level(CompressionLevel::FASTEST));
+        for i in 0..10_000 {
+            store.insert(i, i * i);
+        }
+        store.save()?;
+
+        let bytes = std::fs::read(&path)?;
+        std::fs::write(&path, &bytes[..bytes.len() / 2])?;
+        assert!(Store::<u64, u64>::open(StoreOptions::new(&path)).is_err());
+
+        let (repaired, dropped) = Store::<u64, u64>::repair(StoreOptions::new(&path))?;
+        assert!(dropped > 0);
+        assert_eq!(repaired.len() + dropped, 10_000);
+        assert!(repaired.iter().all(|(k, v)| *v == k * k));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_recover() -> error::Result<()> {
+        use crate::error::RecoveryWarning;
+
+        let path = temp_path("open_recover");
+        let mut store: Store<u64, i64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.insert(2, -2);
+        store.save()?;
+
+        let (recovered, warnings) = Store::<u64, i64>::open_recover(StoreOptions::new(&path))?;
+        assert_eq!(recovered.len(), 2);
+        assert!(warnings.is_empty());
+
+        // A negative value doesn't decode as a u64, so only that entry is lost.
+        let (recovered, warnings) = Store::<u64, u64>::open_recover(StoreOptions::new(&path))?;
+        assert_eq!(recovered.get(&1), Some(&1));
+        assert_eq!(recovered.len(), 1);
+        assert!(matches!(warnings[0], RecoveryWarning::OpenFailed(_)));
+        assert!(matches!(
+            &warnings[1],
+            RecoveryWarning::SkippedEntry { range, key } if !range.is_empty() && key == "2"
+        ));
+
+        // Cutting off the end of the checksum fails the open with an unexpected EOF, but leaves
+        // every entry intact.
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.extend((0..1000).map(|i| (i, i)));
+        store.save()?;
+        let bytes = std::fs::read(&path)?;
+        std::fs::write(&path, &bytes[..bytes.len() - 8])?;
+        let (recovered, warnings) = Store::<u64, u64>::open_recover(StoreOptions::new(&path))?;
+        assert!(matches!(
+            &warnings[0],
+            RecoveryWarning::OpenFailed(error::Error::Io(error))
+                if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(warnings[1], RecoveryWarning::Truncated { .. }));
+        assert_eq!(recovered.len(), 1000);
+
+        std::fs::write(&path, b"junk")?;
+        assert!(Store::<u64, u64>::open_recover(StoreOptions::new(&path)).is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_decode_error_names_key() -> error::Result<()> {
+        let mut store: Store<String, String> = Store::new(StoreOptions::default());
+        store.insert("john".to_string(), "not a person".to_string());
+        let bytes = store.to_bytes()?;
+
+        let result = Store::<String, Person>::from_bytes(&bytes, StoreOptions::default());
+        match result {
+            Err(error::Error::ValueDecode { key, .. }) => assert_eq!(key, "\"john\""),
+            other => panic!("expected a value decode error, got {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_before_overwrite() -> error::Result<()> {
+        let path = temp_path("check-before-overwrite");
+        let options = StoreOptions::new(&path).with_check_before_overwrite(true);
+        let mut store: Store<u64, Person> = Store::new(options.clone());
+        store.insert(5, get_person());
+        store.save()?;
+        store.save()?;
+
+        let mut other: Store<u64, Person> = Store::open(options.clone())?;
+        other.insert(6, get_person());
+        other.save()?;
+
+        let result = store.save();
+        assert!(matches!(result, Err(error::Error::ConcurrentModification)));
+        assert_eq!(Store::<u64, Person>::open(options)?.len(), 2);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "query")]
+    #[test]
+    fn test_query() -> error::Result<()> {
+        use crate::query::Op;
+        use serde_json::json;
+
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.insert(1, get_person());
+        let mut young = get_person();
+        young.age = 20;
+        young.address.state = "Ohio".to_string();
+        store.insert(2, young);
+
+        let found = store.query("address.state", Op::Eq, json!("Illinois"))?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].0, 1);
+
+        let found = store.query("age", Op::Lt, json!(30))?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].0, 2);
+
+        assert!(
+            store
+                .query("address.country", Op::Ne, json!("x"))?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_backup() -> error::Result<()> {
+        let path = temp_path("open-backup");
+        let options = StoreOptions::new(&path).with_backups(2);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        for i in 0..3 {
+            store.insert(i, i);
+            store.save()?;
+        }
+
+        let newest = Store::<u64, u64>::open_backup(options.clone(), 1)?;
+        assert_eq!(newest.len(), 2);
+        assert_eq!(newest.get(&2), None);
+
+        // Saving a backup restores it over the primary file, which rotates into the backups.
+        let oldest = Store::<u64, u64>::open_backup(options.clone(), 2)?;
+        assert_eq!(oldest.keys().collect::<Vec<_>>(), [&0]);
+        oldest.save()?;
+        assert_eq!(Store::<u64, u64>::open(options.clone())?.len(), 1);
+        assert_eq!(Store::<u64, u64>::open_backup(options.clone(), 1)?.len(), 3);
+
+        std::fs::write(backup::backup_path(&path, 2), b"not a store")?;
+        let result = Store::<u64, u64>::open_backup(options, 2);
+        assert!(matches!(result, Err(error::Error::InvalidFile)));
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(backup::backup_path(&path, 1))?;
+        std::fs::remove_file(backup::backup_path(&path, 2))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_resilient() -> error::Result<()> {
+        let path = temp_path("resilient");
+        let options = StoreOptions::new(&path).with_backups(2);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        for i in 0..3 {
+            store.insert(i, i);
+            store.save()?;
+        }
+
+        assert!(backup::backup_path(&path, 2).exists());
+        assert!(!backup::backup_path(&path, 3).exists());
+
+        std::fs::write(&path, b"OGMA garbage")?;
+        let (store, loaded) = Store::<u64, u64>::open_resilient(options.clone())?;
+        assert_eq!(loaded, backup::backup_path(&path, 1));
+        assert_eq!(store.len(), 2);
+
+        let oldest = Store::<u64, u64>::open_backup(options.clone(), 2)?;
+        assert_eq!(oldest.len(), 1);
+        assert!(oldest.needs_save());
+        let result = Store::<u64, u64>::open_backup(options, 3);
+        assert!(matches!(result, Err(error::Error::NoSuchBackup(_))));
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(backup::backup_path(&path, 1))?;
+        std::fs::remove_file(backup::backup_path(&path, 2))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_dry_run() -> error::Result<()> {
+        let path = temp_path("dry-run");
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::new(&path));
+        store.extend((0..10).map(|i| (i, get_person())));
+
+        let preview = store.save_dry_run()?;
+        assert!(!path.exists());
+        assert_eq!(preview.bytes, store.to_bytes()?.len() as u64);
+        assert_eq!(preview.content_hash, store.content_hash()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_compression() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.extend((0..1000).map(|i| (i, get_person())));
+
+        let fast = store.sample_compression(CompressionLevel::FASTEST, 0.1)?;
+        let smallest = store.sample_compression(CompressionLevel::SMALLEST_SIZE, 0.1)?;
+        assert!(smallest < 1.0);
+        assert!(smallest <= fast);
+        assert!(store.sample_compression(CompressionLevel::FAST, 0.0)? > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bare_bytes() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.insert(5, get_person());
+
+        let bytes = store.to_bare_bytes()?;
+        assert_eq!(bytes, store.to_bytes()?[6..]);
+        let bare: Store<u64, Person> = Store::from_bare_bytes(&bytes, StoreOptions::default())?;
+        assert_eq!(bare.get(&5), Some(&get_person()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_compress_bytes() -> error::Result<()> {
+        let mut store: Store<u64, Person> =
+            Store::new(StoreOptions::default().with_min_compress_bytes(1024));
+        store.insert(5, get_person());
+
+        let bytes = store.to_bytes()?;
+        assert_eq!(&bytes[6..8], &[0, 0]);
+        let small: Store<u64, Person> = Store::from_bytes(&bytes, StoreOptions::default())?;
+        assert_eq!(small.get(&5), Some(&get_person()));
+
+        for i in 0..100 {
+            store.insert(i, get_person());
+        }
+        let bytes = store.to_bytes()?;
+        assert_eq!(&bytes[6..8], &[1, 0]);
+        let large: Store<u64, Person> = Store::from_bytes(&bytes, StoreOptions::default())?;
+        assert_eq!(large.len(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect() -> error::Result<()> {
+        let path = temp_path("inspect");
+        let options = StoreOptions::new(&path)
+            .with_compression_level(CompressionLevel::BALANCED)
+            .with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options);
+        store.extend((0..3).map(|i| (i, i)));
+        store.save()?;
+
+        let info = info::inspect(&path)?;
+        assert_eq!(info.compressed, Some(true));
+        assert_eq!(info.compression_level, Some(CompressionLevel::BALANCED));
+        assert_eq!(info.entry_count, Some(3));
+        assert_eq!(info.size, std::fs::metadata(&path)?.len());
+
+        // The count in the header is out of date once delta layers are written on top of it.
+        store.insert(3, 3);
+        store.save_incremental()?;
+        assert_eq!(info::inspect(&path)?.entry_count, None);
+        store.compact()?;
+        assert_eq!(info::inspect(&path)?.entry_count, Some(4));
+
+        std::fs::write(&path, b"not a store")?;
+        assert!(matches!(
+            info::inspect(&path),
+            Err(error::Error::InvalidFile)
+        ));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_compression() -> error::Result<()> {
+        let path = temp_path("no-compression");
+        let options = StoreOptions::new(&path).with_compression_level(CompressionLevel::NONE);
+        let mut store: Store<u64, String> = Store::new(options.clone());
+        store.insert(1, "readable".into());
+        store.save()?;
+
+        let bytes = std::fs::read(&path)?;
+        assert_eq!(&bytes[6..8], &[0, 0]);
+        assert!(bytes.windows(8).any(|window| window == b"readable"));
+        let info = info::inspect(&path)?;
+        assert_eq!(info.compression_level, None);
+        let opened: Store<u64, String> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(opened.get(&1).map(String::as_str), Some("readable"));
+
+        let mut ordered = OrderedStore::new(options);
+        ordered.insert(2u64, "ordered".to_string());
+        ordered.save()?;
+        let opened: Store<u64, String> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(
+            CompressionLevel::from_name("none"),
+            Some(CompressionLevel::NONE)
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut store: Store<u64, u64> = Store::with_capacity(StoreOptions::default(), 1000);
+        assert!(store.capacity() >= 1000);
+        store.reserve(5000);
+        assert!(store.capacity() >= 5000);
+        assert!(!store.needs_save());
+
+        store.extend((0..10).map(|i| (i, i)));
+        store.shrink_to_fit();
+        assert!(store.capacity() < 1000);
+
+        let collected: Store<u64, u64> = (0..1000).map(|i| (i, i)).collect();
+        assert!(collected.capacity() >= 1000);
+    }
+
+    #[test]
+    fn test_try_for_each_mut() {
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::default());
+        store.insert(1, 1);
+        store.insert(2, 2);
+
+        let result = store.try_for_each_mut(|_, v| -> Result<(), ()> {
+            *v *= 10;
+            Ok(())
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(store.get(&2), Some(&20));
+
+        let result = store.try_for_each_mut(|k, _| if *k == 2 { Err(*k) } else { Ok(()) });
+        assert_eq!(result, Err(2));
+    }
+
+    #[test]
+    fn test_stable_order() -> error::Result<()> {
+        let options = StoreOptions::default().with_stable_order(true);
+        let mut a: Store<u64, u64> = Store::new(options.clone());
+        let mut b: Store<u64, u64> = Store::new(options.clone());
+        for i in (0..100).rev() {
+            a.insert(i, i);
+            b.insert(i, i);
+        }
+        assert_eq!(a.to_bytes()?, b.to_bytes()?);
+
+        let reopened: Store<u64, u64> = Store::from_bytes(&a.to_bytes()?, options)?;
+        assert_eq!(reopened.len(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tombstones() -> error::Result<()> {
+        let options = StoreOptions::default().with_tombstones(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.insert(2, 2);
+        store.remove(&1);
+        assert_eq!(store.get(&1), None);
+        assert!(store.is_tombstoned(&1));
+
+        let mut reopened: Store<u64, u64> = Store::from_bytes(&store.to_bytes()?, options)?;
+        assert_eq!(reopened.len(), 1);
+        assert!(reopened.is_tombstoned(&1));
+        assert_eq!(reopened.purge_tombstones(std::time::Duration::ZERO), 1);
+        assert_eq!(reopened.tombstones().count(), 0);
+
+        let path = temp_path("tombstones-incremental");
+        let options = StoreOptions::new(&path)
+            .with_tombstones(true)
+            .with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.extend([(1, 1), (2, 2)]);
+        store.save()?;
+        store.remove(&1);
+        store.save_incremental()?;
+        store.insert(3, 3);
+        assert_eq!(store.purge_tombstones(std::time::Duration::ZERO), 1);
+        store.save_incremental()?;
+        assert!(!store.needs_save());
+        let reopened: Store<u64, u64> = Store::open(options)?;
+        assert_eq!(reopened.tombstones().count(), 0);
+        assert_eq!(reopened.len(), 2);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_save() -> error::Result<()> {
+        let path = temp_path("incremental");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.insert(2, 2);
+        store.save()?;
+
+        store.insert(3, 3);
+        store.remove(&1);
+        store.save_incremental()?;
+        *store.get_mut(&2).unwrap() = 20;
+        store.mark_changed(2);
+        store.save_incremental()?;
+
+        let reopened: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened.get(&2), Some(&20));
+        assert_eq!(reopened.get(&3), Some(&3));
+
+        // A layer torn by a crash is dropped by the next append, from the end found on open.
+        let mut delta = std::fs::OpenOptions::new()
+            .append(true)
+            .open(delta::delta_path(&path))?;
+        std::io::Write::write_all(&mut delta, &[16, 0, 0, 0, 1, 2])?;
+        let mut reopened: Store<u64, u64> = Store::open(options.clone())?;
+        reopened.insert(4, 4);
+        reopened.save_incremental()?;
+        reopened.insert(5, 5);
+        reopened.save_incremental()?;
+        let reopened: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(reopened.len(), 4);
+        assert_eq!(reopened.get(&5), Some(&5));
+
+        reopened.compact()?;
+        assert!(!delta::delta_path(&path).exists());
+        assert_eq!(Store::<u64, u64>::open(options)?.len(), 4);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_save_untracked_changes() -> error::Result<()> {
+        let path = temp_path("incremental-untracked");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.extend([(1, 1), (2, 2)]);
+        store.save()?;
+
+        store
+            .try_for_each_mut(|_, value| -> Result<(), ()> {
+                *value *= 10;
+                Ok(())
+            })
+            .unwrap();
+        store.save_incremental()?;
+        assert!(!store.needs_save());
+        let mut reopened: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(reopened.get(&2), Some(&20));
+
+        // Changes recorded alongside them don't hide the ones that weren't.
+        reopened.insert(1, 100);
+        reopened.iter_mut().for_each(|(_, value)| *value += 1);
+        reopened.save_incremental()?;
+        let mut reopened: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(
+            (reopened.get(&1), reopened.get(&2)),
+            (Some(&101), Some(&21))
+        );
+
+        reopened.map_mut().insert(3, 30);
+        reopened.flush()?;
+        assert!(!reopened.needs_save());
+        assert_eq!(Store::<u64, u64>::open(options.clone())?.get(&3), Some(&30));
+
+        // A restored backup has no changes recorded at all, but still has to be written.
+        let options = options.with_backups(1);
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        store.insert(4, 40);
+        store.save()?;
+        let restored = Store::<u64, u64>::open_backup(options.clone(), 1)?;
+        restored.save_incremental()?;
+        assert!(!restored.needs_save());
+        assert_eq!(Store::<u64, u64>::open(options)?.len(), 3);
+
+        std::fs::remove_file(backup::backup_path(&path, 1))?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_compact() -> error::Result<()> {
+        let path = temp_path("auto-compact");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(0, 0);
+        store.save()?;
+        for i in 1..100 {
+            store.insert(i, i);
+        }
+        store.save_incremental()?;
+        assert!(delta::delta_path(&path).exists());
+
+        // The delta file is far larger than the snapshot, but within a generous ratio.
+        let reopened: Store<u64, u64> =
+            Store::open(options.clone().with_auto_compact_ratio(Some(100.0)))?;
+        assert!(delta::delta_path(&path).exists());
+        drop(reopened);
+
+        let reopened: Store<u64, u64> = Store::open(options.with_auto_compact_ratio(Some(1.0)))?;
+        assert!(!delta::delta_path(&path).exists());
+        assert_eq!(reopened.len(), 100);
+        assert!(!reopened.needs_save());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let mut store: Store<u64, (String, u32)> = Store::new(StoreOptions::default());
+        store.insert(1, ("Illinois".into(), 30));
+        store.add_index("state", |(state, _): &(String, u32)| state.clone());
+        store.insert(2, ("Illinois".into(), 40));
+        store.insert(3, ("Ohio".into(), 50));
+
+        let illinois = |store: &Store<u64, (String, u32)>| {
+            let mut keys: Vec<u64> = store
+                .query_index("state", &"Illinois".to_string())
+                .map(|(key, _)| *key)
+                .collect();
+            keys.sort_unstable();
+            keys
+        };
+        assert_eq!(illinois(&store), [1, 2]);
+
+        store.insert(1, ("Ohio".into(), 30));
+        store.remove(&2);
+        assert!(illinois(&store).is_empty());
+        assert_eq!(store.query_index("state", &"Ohio".to_string()).count(), 2);
+        assert_eq!(store.query_index("state", &0u32).count(), 0);
+
+        store.get_mut(&3).unwrap().0 = "Illinois".into();
+        assert_eq!(illinois(&store), [3]);
+        store.rebuild_indexes();
+        assert_eq!(illinois(&store.clone()), [3]);
+    }
+
+    #[test]
+    fn test_secondary_index_mutable_access() {
+        let mut store: Store<u64, (String, u32)> = Store::new(StoreOptions::default());
+        store.insert(1, ("Illinois".into(), 30));
+        store.insert(2, ("Illinois".into(), 40));
+        store.add_index("state", |(state, _): &(String, u32)| state.clone());
+
+        let count = |store: &Store<u64, (String, u32)>, state: &str| {
+            store.query_index("state", &state.to_string()).count()
+        };
+
+        store
+            .entry(1)
+            .and_modify(|(state, _)| *state = "Ohio".into());
+        assert_eq!(count(&store, "Illinois"), 1);
+        assert_eq!(count(&store, "Ohio"), 1);
+
+        // The next change puts the modified entry back into the index under its new state.
+        store.insert(3, ("Iowa".into(), 50));
+        assert_eq!(count(&store, "Illinois"), 1);
+        assert_eq!(count(&store, "Ohio"), 1);
+
+        store.get_mut(&1).unwrap().0 = "Iowa".into();
+        *store.get_mut_or_default(2) = ("Ohio".into(), 40);
+        assert_eq!(count(&store, "Illinois"), 0);
+        assert_eq!(count(&store, "Ohio"), 1);
+        assert_eq!(count(&store, "Iowa"), 2);
+
+        store
+            .iter_mut()
+            .for_each(|(_, (state, _))| *state = "Utah".into());
+        assert_eq!(count(&store, "Utah"), 3);
+        store.remove(&1);
+        assert_eq!(count(&store, "Utah"), 2);
+        assert_eq!(count(&store, "Iowa"), 0);
+    }
+
+    #[test]
+    fn test_merge() -> error::Result<()> {
+        let shard =
+            |entries: &[(u64, u64)]| -> Store<u64, u64> { entries.iter().copied().collect() };
+
+        let mut store = shard(&[(1, 1), (2, 2)]);
+        assert_eq!(
+            store.merge(shard(&[(2, 20), (3, 30)]), OnDuplicate::Skip)?,
+            1
+        );
+        assert_eq!(store.get(&2), Some(&2));
+        assert_eq!(store.len(), 3);
+
+        store.merge_with(shard(&[(1, 10), (4, 40)]), |_, current, other| {
+            current + other
+        });
+        assert_eq!(store.get(&1), Some(&11));
+        assert_eq!(store.get(&4), Some(&40));
+        assert!(store.needs_save());
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_all() -> error::Result<()> {
+        let mut store: Store<String, u64> = Store::new(StoreOptions::default());
+        store.insert("a".into(), 1);
+
+        let entries = [("a", 10), ("b", 2), ("b", 20)].map(|(key, value)| (key.into(), value));
+        assert_eq!(store.insert_all(entries.clone(), OnDuplicate::Skip)?, 1);
+        assert_eq!(store.get("a"), Some(&1));
+        assert_eq!(store.get("b"), Some(&2));
+
+        // The first key already present is reported, and the entries before it are kept.
+        let mut store: Store<String, u64> = Store::new(StoreOptions::default());
+        let result = store.insert_all(entries, OnDuplicate::Error);
+        assert!(matches!(result, Err(error::Error::DuplicateKey(key)) if key == "\"b\""));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("b"), Some(&2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_promote() -> error::Result<()> {
+        let staging = temp_path("promote-staging");
+        let live = temp_path("promote-live");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&live));
+        store.insert(1, 1);
+        store.save()?;
+
+        // The live store's stale delta file is replaced by the staged one.
+        let options = StoreOptions::new(&staging).with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options);
+        store.insert(1, 10);
+        store.save()?;
+        store.insert(2, 20);
+        store.save_incremental()?;
+        std::fs::write(delta::delta_path(&live), b"stale")?;
+
+        Store::<u64, u64>::promote(&staging, &live)?;
+        assert!(!staging.exists());
+        assert!(!delta::delta_path(&staging).exists());
+        let promoted: Store<u64, u64> = Store::open(StoreOptions::new(&live))?;
+        assert_eq!(promoted.get(&1), Some(&10));
+        assert_eq!(promoted.get(&2), Some(&20));
+
+        // A file that isn't a store is never moved into place.
+        std::fs::write(&staging, b"not a store")?;
+        let result = Store::<u64, u64>::promote(&staging, &live);
+        assert!(matches!(result, Err(error::Error::InvalidFile)));
+        assert!(staging.exists());
+        assert_eq!(Store::<u64, u64>::open(StoreOptions::new(&live))?.len(), 2);
+
+        std::fs::remove_file(staging)?;
+        std::fs::remove_file(delta::delta_path(&live))?;
+        std::fs::remove_file(live)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_file() -> error::Result<()> {
+        let path = temp_path("merge-file");
+        let mut other: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        other.insert(1, 10);
+        other.insert(2, 20);
+        other.save()?;
+
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::default());
+        store.insert(2, 0);
+        store.insert(3, 30);
+        let options = StoreOptions::new(&path);
+        assert_eq!(store.merge_file(&options, OnDuplicate::Skip)?, 1);
+        assert_eq!(store.get(&2), Some(&0));
+        assert_eq!(store.merge_file(&options, OnDuplicate::Overwrite)?, 2);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(&2), Some(&20));
+        assert!(matches!(
+            store.merge_file(&options, OnDuplicate::Error),
+            Err(error::Error::DuplicateKey(_))
+        ));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_for_each_entry() -> error::Result<()> {
+        let path = temp_path("for-each-entry");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 10);
+        store.insert(2, 20);
+        store.save()?;
+
+        let mut sum = 0;
+        Store::<u64, u64>::for_each_entry(&StoreOptions::new(&path), |_, value| {
+            sum += value;
+            Ok(())
+        })?;
+        assert_eq!(sum, 30);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_dir() -> error::Result<()> {
+        let dir = temp_path("scan-dir");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("notes.txt"), "not a store")?;
+
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::new(dir.join("a.ogma")));
+        store.insert(1, get_person());
+        store.insert(2, get_person());
+        store.save()?;
+
+        let stores = info::scan_dir(&dir)?;
+        assert_eq!(stores.len(), 1);
+        assert_eq!(stores[0].0, dir.join("a.ogma"));
+        assert_eq!(stores[0].1.entry_count, Some(2));
+        assert_eq!(stores[0].1.compressed, Some(true));
+        assert_eq!(
+            stores[0].1.compression_level,
+            Some(CompressionLevel::DEFAULT)
+        );
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_checksums() -> error::Result<()> {
+        let options = StoreOptions::default().with_entry_checksums(true);
+        let mut store: Store<u64, Person> = Store::new(options.clone());
+        store.insert(1, get_person());
+        store.insert(2, get_person());
+
+        let mut store: Store<u64, Person> = Store::from_bytes(&store.to_bytes()?, options)?;
+        assert!(store.verify_entry(&1)?);
+        store.get_mut(&2).unwrap().age += 1;
+        assert!(!store.verify_entry(&2)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preallocate() -> error::Result<()> {
+        let path = temp_path("preallocate");
+        let mut store: Store<u64, String> =
+            Store::new(StoreOptions::new(&path).with_preallocate(true));
+        store.insert(1, "a".repeat(1000));
+        store.save()?;
+
+        // The second save is smaller than the space reserved for it and must be trimmed.
+        store.remove(&1);
+        store.save()?;
+        assert_eq!(
+            std::fs::metadata(&path)?.len(),
+            store.to_bytes()?.len() as u64
+        );
+        assert!(Store::<u64, String>::open(StoreOptions::new(&path))?.is_empty());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch() -> error::Result<()> {
+        let path = temp_path("watch");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let watcher = Store::<u64, u64>::watch(
+            StoreOptions::new(&path),
+            std::time::Duration::from_millis(10),
+            move |store| sender.send(store.map(|store| store.len())).unwrap(),
+        )?;
+
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.insert(2, 2);
+        store.save()?;
+
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(receiver.recv_timeout(timeout).unwrap()?, 2);
+
+        drop(watcher);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_autosave() -> error::Result<()> {
+        let path = temp_path("autosave");
+        let options = StoreOptions::new(&path).with_autosave(true);
+        {
+            let mut store: Store<u64, u64> = Store::new(options.clone());
+            store.try_insert(1, 1)?;
+            store.try_insert(2, 2)?;
+            store.try_remove(&1)?;
+            assert!(!store.needs_save());
+        }
+
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!((store.get(&1), store.get(&2)), (None, Some(&2)));
+
+        store.insert(3, 3);
+        store.flush()?;
+        assert!(!store.needs_save());
+        let store: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(store.len(), 2);
+
+        // Dropping the store flushes changes made without `try_insert`, but consuming it doesn't.
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        store.insert(4, 4);
+        drop(store);
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(store.get(&4), Some(&4));
+        store.insert(5, 5);
+        assert_eq!(store.into_iter().count(), 4);
+        assert_eq!(Store::<u64, u64>::open(options)?.len(), 3);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction() -> error::Result<()> {
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::default());
+        store.extend([(1, 1), (2, 2)]);
+        let before = store.to_bytes()?;
+
+        let result = store.transaction(|txn| {
+            txn.insert(3, 3);
+            txn.remove(1);
+            assert_eq!((txn.get(&1), txn.get(&3)), (None, Some(&3)));
+            Err(error::Error::Validation("rejected".into()))
+        });
+        assert!(result.is_err());
+        assert_eq!(store.to_bytes()?, before);
+
+        store.transaction(|txn| {
+            txn.insert(3, 3);
+            txn.remove(1);
+            Ok(())
+        })?;
+        assert_eq!((store.get(&1), store.get(&3)), (None, Some(&3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_and_retain() {
+        let mut store: Store<u64, u64> = (0..10).map(|i| (i, i)).collect();
+        store.extend([(10, 10), (11, 11)]);
+        assert_eq!(store.len(), 12);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        store.set_event_sender(Some(sender));
+        store.retain(|key, value| {
+            *value *= 2;
+            key % 2 == 0
+        });
+        assert_eq!(store.len(), 6);
+        assert_eq!(store.get(&4), Some(&8));
+        assert_eq!(receiver.try_iter().count(), 6);
+    }
+
+    #[test]
+    fn test_get_many() {
+        let mut store: Store<u64, u64> = (0..4).map(|i| (i, i)).collect();
+        assert_eq!(store.get_many(&[&2, &9, &0]), [Some(&2), None, Some(&0)]);
+        assert!(store.get_many(&[]).is_empty());
+
+        let values = store.get_many_mut(&[&3, &7, &1]).unwrap();
+        assert!(values[1].is_none());
+        for value in values.into_iter().flatten() {
+            *value += 10;
+        }
+        assert_eq!((store.get(&3), store.get(&1)), (Some(&13), Some(&11)));
+
+        assert!(store.get_many_mut(&[&1, &2, &1]).is_none());
+        assert_eq!(store.get_many_mut(&[]).map(|values| values.len()), Some(0));
+    }
+
+    #[test]
+    fn test_ttl() -> error::Result<()> {
+        let path = temp_path("ttl");
+        let options = StoreOptions::new(&path).with_incremental(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.insert_with_ttl(2, 2, std::time::Duration::ZERO);
+        store.insert_with_ttl(3, 3, std::time::Duration::from_secs(3600));
+        assert_eq!((store.get(&2), store.contains_key(&2)), (None, false));
+        assert_eq!(store.get(&3), Some(&3));
+        assert!(store.expires_at(&1).is_none());
+        store.save()?;
+
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(store.get(&2), None);
+        assert!(store.expires_at(&3).is_some());
+        store.insert_with_ttl(4, 4, std::time::Duration::ZERO);
+        store.insert(3, 3);
+        store.save_incremental()?;
+
+        let mut store: Store<u64, u64> = Store::open(options)?;
+        assert!(store.expires_at(&3).is_none());
+        assert_eq!(store.get(&4), None);
+        assert_eq!(store.purge_expired(), 2);
+        assert_eq!(store.len(), 2);
+
+        std::fs::remove_file(delta::delta_path(&path))?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl_mutable_access() {
+        let expired = |store: &mut Store<u64, u64>| {
+            store.insert_with_ttl(1, 10, std::time::Duration::ZERO);
+        };
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::default());
+
+        expired(&mut store);
+        assert_eq!(store.increment(&1, 1), 1);
+        assert!(store.expires_at(&1).is_none());
+
+        expired(&mut store);
+        assert_eq!(*store.entry(1).or_insert(5), 5);
+        expired(&mut store);
+        assert_eq!(
+            *store.entry(1).and_modify(|value| *value += 1).or_default(),
+            0
+        );
+
+        expired(&mut store);
+        assert_eq!(store.get_mut(&1), None);
+        assert!(!store.map.contains_key(&1));
+
+        expired(&mut store);
+        assert_eq!(*store.get_mut_or_default(1), 0);
+        expired(&mut store);
+        assert_eq!(store.get_many_mut(&[&1]), Some(vec![None]));
+
+        expired(&mut store);
+        assert_eq!(store.insert(1, 2), None);
+    }
+
+    #[test]
+    fn test_dictionary() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        for i in 0..1000 {
+            let mut person = get_person();
+            person.age = (i % 100) as _;
+            store.insert(i, person);
+        }
+        let dictionary = store.train_dictionary(4096)?;
+
+        let options = StoreOptions::default().with_dictionary(Some(dictionary.clone()));
+        let mut store: Store<u64, Person> = Store::new(options);
+        store.insert(1000, get_person());
+        let bytes = store.to_bytes()?;
+
+        let options = StoreOptions::default().with_dictionary(Some(dictionary));
+        let reopened: Store<u64, Person> = Store::from_bytes(&bytes, options)?;
+        assert_eq!(reopened.get(&1000), Some(&get_person()));
+
+        let result = Store::<u64, Person>::from_bytes(&bytes, StoreOptions::default());
+        assert!(matches!(result, Err(error::Error::DictionaryRequired)));
+        let options = StoreOptions::default().with_dictionary(Some(vec![0; 16]));
+        let result = Store::<u64, Person>::from_bytes(&bytes, options);
+        assert!(matches!(result, Err(error::Error::DictionaryMismatch)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal() -> error::Result<()> {
+        use std::io::Write;
+
+        let path = temp_path("wal");
+        let options = StoreOptions::new(&path).with_wal(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.try_insert(1, 1)?;
+        store.try_insert(2, 2)?;
+        store.try_remove(&1)?;
+        assert!(delta::delta_path(&path).exists());
+
+        // A record with its length intact but a torn body is dropped on replay.
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(delta::delta_path(&path))?;
+        log.write_all(&8u32.to_le_bytes())?;
+        log.write_all(&[0; 8])?;
+        drop(log);
+
+        let mut store: Store<u64, u64> = Store::open(options.clone())?;
+        assert_eq!(store.iter().collect::<Vec<_>>(), [(&2, &2)]);
+        store.try_insert(3, 3)?;
+
+        let store: Store<u64, u64> = Store::open(options)?;
+        assert_eq!(store.len(), 2);
+        store.save()?;
+        assert!(!delta::delta_path(&path).exists());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_round_trip() -> error::Result<()> {
+        let mut store: Store<u64, Person> = Store::new(StoreOptions::default());
+        store.insert(5, get_person());
+        store.insert(1, get_person());
+
+        let mut json = Vec::new();
+        store.export_json(&mut json)?;
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[\n  [1,{"));
+        assert_eq!(json.lines().count(), 4);
+        assert_eq!(store.to_json()?, json);
+        let parsed: Store<u64, Person> = Store::from_json(&json, StoreOptions::default())?;
+        assert_eq!(parsed.len(), 2);
+
+        let imported: Store<u64, Person> =
+            Store::import_json(json.as_bytes(), StoreOptions::default())?;
+        assert_eq!(imported.content_hash()?, store.content_hash()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_path() {
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::default());
+        store.insert(1, 1);
+        assert!(matches!(store.save(), Err(error::Error::InvalidPath)));
+        let result = Store::<u64, u64>::open(StoreOptions::default());
+        assert!(matches!(result, Err(error::Error::InvalidPath)));
+    }
+
+    #[test]
+    fn test_needs_save() -> error::Result<()> {
+        let path = temp_path("needs_save");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        assert!(!store.needs_save());
+        assert!(!store.differs_from_disk()?);
+
+        store.insert(1, 1);
+        assert!(store.needs_save());
+        assert!(store.differs_from_disk()?);
+
+        store.save()?;
+        assert!(!store.needs_save());
+        assert!(!store.differs_from_disk()?);
+
+        // A change that is undone still needs a save, but matches the file.
+        store.insert(2, 2);
+        store.remove(&2);
+        assert!(store.needs_save());
+        assert!(!store.differs_from_disk()?);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_store() -> error::Result<()> {
+        let path = temp_path("multi");
+        let mut multi = MultiStore::new(StoreOptions::new(&path));
+        multi
+            .section::<String, Person>("people")?
+            .insert("cat".into(), get_person());
+        multi.section::<u64, bool>("flags")?.insert(7, true);
+        multi.save()?;
+
+        let mut multi = MultiStore::open(StoreOptions::new(&path))?;
+        assert_eq!(
+            multi.section_names().collect::<Vec<_>>(),
+            ["flags", "people"]
+        );
+        assert_eq!(multi.section::<u64, bool>("flags")?.get(&7), Some(&true));
+        assert!(matches!(
+            multi.section::<u64, u64>("flags"),
+            Err(error::Error::SectionType(_))
+        ));
+        assert_eq!(multi.section::<String, Person>("people")?.len(), 1);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_into_new_directory() -> error::Result<()> {
+        let dir = temp_path("new_dir");
+        let path = dir.join("nested").join("store.ogma");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.save()?;
+        assert_eq!(Store::<u64, u64>::open(StoreOptions::new(&path))?.len(), 1);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_summary() {
+        let mut store: Store<Vec<u8>, u64> = Store::new(StoreOptions::new("binary.ogma"));
+        store.insert(vec![0xde, 0xad], 1);
+        assert_eq!(
+            store.debug_summary(),
+            r#"Store { path: "binary.ogma", entries: 1, bytes: 8, keys: [dead] }"#
+        );
+    }
+
+    #[test]
+    fn test_open_or_init() -> error::Result<()> {
+        let path = temp_path("open_or_init");
+        let init = || HashMap::from([(1, 1)]);
+        let mut store: Store<u64, u64> = Store::open_or_init(StoreOptions::new(&path), init)?;
+        assert_eq!(store.get(&1), Some(&1));
+        assert!(store.needs_save());
+
+        store.insert(1, 2);
+        store.save()?;
+        let store: Store<u64, u64> = Store::open_or_init(StoreOptions::new(&path), init)?;
+        assert_eq!(store.get(&1), Some(&2));
+
+        let result = Store::<u64, u64>::open_or_init(StoreOptions::new(""), init);
+        assert!(matches!(result, Err(error::Error::InvalidPath)));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_directory() {
+        let result = Store::<u64, u64>::open(StoreOptions::new(std::env::temp_dir()));
+        assert!(matches!(result, Err(error::Error::NotAFile(_))));
+    }
+
+    #[test]
+    fn test_save_all() -> error::Result<()> {
+        let (first_path, second_path) = (temp_path("save_all_1"), temp_path("save_all_2"));
+        let mut first: Store<u64, u64> = Store::new(StoreOptions::new(&first_path));
+        let mut second: Store<String, Person> =
+            Store::new(StoreOptions::new(&second_path).with_check_before_overwrite(true));
+        first.insert(1, 1);
+        second.insert("cat".into(), get_person());
+        batch::save_all(&[&first, &second])?;
+        assert!(first_path.is_file() && second_path.is_file());
+
+        // A conflict in one store keeps the other from being saved.
+        std::fs::write(&second_path, b"changed")?;
+        first.insert(2, 2);
+        let result = batch::save_all(&[&first, &second]);
+        assert!(matches!(result, Err(error::Error::ConcurrentModification)));
+        assert_eq!(
+            Store::<u64, u64>::open(StoreOptions::new(&first_path))?.len(),
+            1
+        );
+
+        std::fs::remove_file(first_path)?;
+        std::fs::remove_file(second_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_verified() -> error::Result<()> {
+        let mut store: Store<String, Person> = Store::new(StoreOptions::default());
+        store.insert("cat".into(), get_person());
+        let bytes = store.to_bytes()?;
+
+        let mut copy = Vec::new();
+        let copied = Store::<String, Person>::copy_verified(bytes.as_slice(), &mut copy)?;
+        assert_eq!(copied, bytes.len() as u64);
+        assert_eq!(copy, bytes);
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(Store::<String, Person>::copy_verified(truncated, std::io::sink()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_mut_or_default() {
+        let mut store: Store<&str, u64> = Store::new(StoreOptions::default());
+        *store.get_mut_or_default("a") += 1;
+        *store.get_mut_or_default("a") += 1;
+        assert_eq!(store.get("a"), Some(&2));
+    }
+
+    #[test]
+    fn test_next_id() -> error::Result<()> {
+        let path = temp_path("next_id");
+        let mut store: Store<u64, String> =
+            Store::new(StoreOptions::new(&path).with_incremental(true));
+        let id = store.next_id();
+        store.insert(id, "first".into());
+        store.save()?;
+
+        // Ids issued after a full save are persisted by incremental saves.
+        assert_eq!(store.next_id(), 2);
+        store.save_incremental()?;
+
+        let mut store: Store<u64, String> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(store.last_id(), 2);
+        assert_eq!(store.next_id(), 3);
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(delta::delta_path(&path))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_access_mode() -> error::Result<()> {
+        let path = temp_path("access_mode");
+        let writer_options = StoreOptions::new(&path).with_access_mode(AccessMode::Write);
+        let mut writer: Store<u64, u64> = Store::new(writer_options);
+        writer.insert(1, 1);
+        writer.save()?;
+
+        let reader_options = StoreOptions::new(&path).with_access_mode(AccessMode::Read);
+        let reader = Store::<u64, u64>::open(reader_options.clone())?;
+        let other_reader = Store::<u64, u64>::open(reader_options)?;
+        assert!(matches!(writer.save(), Err(error::Error::Locked)));
+
+        drop((reader, other_reader));
+        writer.save()?;
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(lock::lock_path(&path))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_base64_round_trip() -> error::Result<()> {
+        for len in 0..4 {
+            let mut store: Store<u64, String> = Store::new(StoreOptions::default());
+            store.insert(1, "x".repeat(len));
+            let encoded = store.to_base64()?;
+            let decoded = Store::<u64, String>::from_base64(&encoded, StoreOptions::default())?;
+            assert_eq!(decoded.get(&1), store.get(&1));
+        }
+
+        let result = Store::<u64, String>::from_base64("not base64!", StoreOptions::default());
+        assert!(matches!(result, Err(error::Error::InvalidBase64)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_access() {
+        let mut store: Store<u64, u64> =
+            Store::new(StoreOptions::default().with_track_access(true));
+        store.insert(1, 1);
+        assert_eq!(store.get_tracked(&1), Some(&1));
+        assert_eq!(store.get_tracked(&1), Some(&1));
+
+        let meta = store.entry_metadata(&1).unwrap();
+        assert!(meta.inserted_at.is_some() && meta.last_accessed_at.is_some());
+        assert_eq!(meta.access_count, 2);
+
+        store.remove(&1);
+        assert_eq!(store.entry_metadata(&1), None);
+    }
+
+    #[test]
+    fn test_maintain() -> error::Result<()> {
+        let path = temp_path("maintain");
+        let options = StoreOptions::new(&path)
+            .with_incremental(true)
+            .with_tombstones(true);
+        let mut store: Store<u64, String> = Store::new(options);
+        store.insert(1, "a".repeat(100));
+        store.save()?;
+        store.insert(2, "b".repeat(100));
+        store.remove(&1);
+        store.save_incremental()?;
+        let bytes_before = store.disk_size()?;
+        assert!(bytes_before > std::fs::metadata(&path)?.len());
+
+        let report = store.maintain(std::time::Duration::ZERO)?;
+        assert_eq!(report.bytes_before, bytes_before);
+        assert_eq!(report.tombstones_purged, 1);
+        assert_eq!(report.bytes_after, std::fs::metadata(&path)?.len());
+        assert!(!delta::delta_path(&path).exists());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_sorted_runs() -> error::Result<()> {
+        let (a, b) = (temp_path("run-a"), temp_path("run-b"));
+        let path = temp_path("merged-runs");
+        Store::<u64, u64>::write_sorted_run(&a, (0..100).step_by(2).map(|i| (i, i)))?;
+        Store::<u64, u64>::write_sorted_run(&b, (0..100).step_by(3).map(|i| (i, i * 10)))?;
+        let unsorted = Store::<u64, u64>::write_sorted_run(&b, [(2, 2), (1, 1)]);
+        assert!(matches!(unsorted, Err(error::Error::UnsortedRun)));
+        Store::<u64, u64>::write_sorted_run(&b, (0..100).step_by(3).map(|i| (i, i * 10)))?;
+
+        let merged = Store::<u64, u64>::merge_runs(&[&a, &b], &StoreOptions::new(&path))?;
+        let store: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(merged, 67);
+        assert_eq!(store.len(), 67);
+        assert_eq!(store[&4], 4);
+        assert_eq!(store[&6], 60);
+        assert_eq!(store[&9], 90);
+
+        std::fs::remove_file(a)?;
+        std::fs::remove_file(b)?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_equivalent() -> error::Result<()> {
+        let (a, b) = (temp_path("equivalent-a"), temp_path("equivalent-b"));
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&a));
+        store.extend((0..100).map(|i| (i, i)));
+        store.save()?;
+        let options = StoreOptions::new(&b).with_compression_level(CompressionLevel::SMALLEST_SIZE);
+        Store::<u64, u64>::from_iter_with_options((0..100).rev().map(|i| (i, i)), options)
+            .save()?;
+        assert!(Store::<u64, u64>::files_equivalent(&a, &b)?);
+
+        store.insert(100, 100);
+        store.save()?;
+        assert!(!Store::<u64, u64>::files_equivalent(&a, &b)?);
+
+        std::fs::remove_file(a)?;
+        std::fs::remove_file(b)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_mismatch() -> error::Result<()> {
+        let path = temp_path("checksum-mismatch");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.set_metadata(Some(b"metadata".to_vec()));
+        store.save()?;
+
+        // The metadata isn't otherwise validated, so only the checksum catches the change.
+        let mut bytes = std::fs::read(&path)?;
+        bytes[12] ^= 1;
+        std::fs::write(&path, &bytes)?;
+        let result = Store::<u64, u64>::open(StoreOptions::new(&path));
+        assert!(matches!(result, Err(error::Error::ChecksumMismatch { .. })));
+
+        bytes[12] ^= 1;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let result = Store::<u64, u64>::from_bytes(&bytes, StoreOptions::default());
+        assert!(matches!(result, Err(error::Error::ChecksumMismatch { .. })));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_validated() -> error::Result<()> {
+        let path = temp_path("validated");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 2);
+        store.save()?;
+
+        let options = StoreOptions::new(&path);
+        let store = Store::<u64, u64>::open_validated(options.clone(), |store| {
+            match store.iter().all(|(key, value)| value > key) {
+                true => Ok(()),
+                false => Err("values must exceed their keys"),
+            }
+        })?;
+        assert_eq!(store.len(), 1);
+
+        let result = Store::<u64, u64>::open_validated(options, |_| Err("always fails"));
+        assert!(
+            matches!(result, Err(error::Error::Validation(message)) if message == "always fails")
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() -> error::Result<()> {
+        let path = temp_path("reload");
+        let mut saved: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        saved.insert(1, 1);
+        saved.save()?;
+
+        let mut store: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+        store.insert(2, 2);
+        saved.insert(3, 3);
+        saved.save()?;
+
+        store.reload()?;
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&3), Some(&3));
+        assert!(!store.contains_key(&2));
+        assert!(!store.needs_save());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_tag_mismatch() -> error::Result<()> {
+        let path = temp_path("type-tag");
+        let options = StoreOptions::new(&path).with_type_tag(Some(1));
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.save()?;
+
+        assert_eq!(Store::<u64, u64>::open(options.clone())?.len(), 1);
+        assert_eq!(Store::<u64, u64>::open(StoreOptions::new(&path))?.len(), 1);
+        let result = Store::<String, u64>::open(options.with_type_tag(Some(2)));
+        assert!(matches!(
+            result,
+            Err(error::Error::TypeMismatch {
+                expected: 2,
+                found: 1
+            })
+        ));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry() -> error::Result<()> {
+        let path = temp_path("entry");
+        let mut store: Store<u64, Vec<u64>> =
+            Store::new(StoreOptions::new(&path).with_incremental(true));
+        store.entry(1).or_insert_with(|| vec![1]);
+        store.entry(1).or_default().push(2);
+        store.entry(2).and_modify(|values| values.push(3));
+        assert_eq!(store.get(&1), Some(&vec![1, 2]));
+        assert!(!store.contains_key(&2));
+        store.save()?;
+
+        store.entry(1).and_modify(|values| values.clear());
+        store.save_incremental()?;
+        let reopened: Store<u64, Vec<u64>> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(reopened.get(&1), Some(&vec![]));
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(delta::delta_path(&path))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_values() -> error::Result<()> {
+        let path = temp_path("map-values");
+        let options = StoreOptions::new(&path)
+            .with_incremental(true)
+            .with_check_before_overwrite(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.set_metadata(Some(b"v1".to_vec()));
+        store.save()?;
+        let id = store.next_id();
+        store.insert(id + 1, 2);
+        store.save_incremental()?;
+
+        // The delta file holds `u64` values, so the flush has to write a full snapshot.
+        let store = store.map_values(|value| value.to_string());
+        store.flush()?;
+        assert!(!delta::delta_path(&path).exists());
+
+        let reopened: Store<u64, String> = Store::open(options)?;
+        assert_eq!(reopened.get(&2), Some(&"2".to_string()));
+        assert_eq!(reopened.last_id(), 1);
+        assert_eq!(reopened.metadata(), Some(&b"v1"[..]));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordered_store() -> error::Result<()> {
+        let path = temp_path("ordered");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.extend((0..100).map(|i| (i * 10, i)));
+        store.save()?;
+
+        let mut ordered: OrderedStore<u64, u64> = OrderedStore::open(StoreOptions::new(&path))?;
+        let window: Vec<u64> = ordered.range(200..250).map(|(_, value)| *value).collect();
+        assert_eq!(window, [20, 21, 22, 23, 24]);
+        assert_eq!(ordered.first_key_value(), Some((&0, &0)));
+        assert_eq!(ordered.last_key_value(), Some((&990, &99)));
+
+        ordered.insert(5, 5);
+        ordered.save()?;
+        let reopened: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(reopened.len(), 101);
+        assert_eq!(reopened.get(&5), Some(&5));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_hasher() -> error::Result<()> {
+        type Hasher = std::hash::BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+
+        let path = temp_path("custom-hasher");
+        let mut store: Store<u64, u64, Hasher> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.save()?;
+
+        let reopened: Store<u64, u64> = Store::open(StoreOptions::new(&path))?;
+        assert_eq!(reopened.get(&1), Some(&1));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterate_entries() {
+        let mut store: Store<u64, u64> =
+            Store::from_iter_with_options((0..5).map(|i| (i, i)), StoreOptions::default());
+        store.iter_mut().for_each(|(_, value)| *value *= 2);
+
+        let mut sum = 0;
+        for (key, value) in &store {
+            assert_eq!(*value, key * 2);
+            sum += value;
+        }
+        assert_eq!(sum, 20);
+
+        let mut entries: Vec<(u64, u64)> = store.into_iter().collect();
+        entries.sort_unstable();
+        assert_eq!(entries[4], (4, 8));
+    }
+
+    #[test]
+    fn test_from_iter_with_options() {
+        let options = StoreOptions::new(temp_path("from-iter")).with_stable_order(true);
+        let store: Store<i32, &str> =
+            Store::from_iter_with_options([(2, "b"), (1, "a"), (2, "c")], options.clone());
+        assert_eq!(store.options().path, options.path);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store[&2], "c");
+    }
+
+    #[test]
+    fn test_metrics() -> error::Result<()> {
+        let path = temp_path("metrics");
+        let options = StoreOptions::new(&path).with_collect_metrics(true);
+        let mut store: Store<u64, u64> = Store::new(options.clone());
+        store.insert(1, 1);
+        store.insert(2, 2);
+        store.remove(&3);
+        store.save()?;
+
+        let metrics = store.metrics();
+        assert_eq!(metrics.insert.count, 2);
+        assert_eq!(metrics.remove.count, 1);
+        assert_eq!(metrics.save.count, 1);
+        assert!(metrics.save.max >= metrics.save.average());
+        assert_eq!(Store::<u64, u64>::open(options)?.metrics().open.count, 1);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_replaces_existing_file() -> error::Result<()> {
+        let path = temp_path("replace");
+        let mut store: Store<u64, u64> = Store::new(StoreOptions::new(&path));
+        store.insert(1, 1);
+        store.save()?;
+
+        // Hold the file open while replacing it, as a reader in another process might.
+        let _reader = std::fs::File::open(&path)?;
+        store.insert(2, 2);
+        store.save()?;
+        assert_eq!(Store::<u64, u64>::open(StoreOptions::new(&path))?.len(), 2);
+
+        drop(_reader);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ogma-{name}-{}.ogma", std::process::id()))
+    }
+
     fn get_person() -> Person {
         Person {
             first_name: "John".to_string(),
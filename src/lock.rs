@@ -0,0 +1,87 @@
+use crate::error::{Error, Result};
+use crate::store::{AccessMode, Store, StoreOptions};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::hash::{BuildHasher, Hash};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Returns the path of the lock file for the store at `path`, e.g. `data.ogma.lock`.
+/// The file is created on first use and left in place, since removing it could let two
+/// processes lock different files.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// A lock held on a store's lock file, released once every clone of the store is dropped.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FileLock {
+    _file: Option<Arc<File>>,
+}
+
+impl FileLock {
+    /// Takes the shared lock that `open` holds for `AccessMode::Read`, or nothing for other modes.
+    pub(crate) fn for_open(options: &StoreOptions) -> Result<Self> {
+        match options.access_mode {
+            Some(AccessMode::Read) => Ok(Self {
+                _file: Some(Arc::new(acquire(options, true)?)),
+            }),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Takes the exclusive lock held while saving, or nothing if `access_mode` is unset.
+    /// A store opened with `AccessMode::Read` conflicts with its own shared lock, so it can't save.
+    pub(crate) fn lock_for_write(&self) -> Result<Option<File>> {
+        if self.options.access_mode.is_none() {
+            return Ok(None);
+        }
+
+        if self.options.create_dirs
+            && let Some(parent) = self.options.path.parent()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        acquire(&self.options, false).map(Some)
+    }
+}
+
+/// Locks the lock file for `options.path`, retrying until `options.lock_timeout` has passed.
+fn acquire(options: &StoreOptions, shared: bool) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path(&options.path))?;
+
+    let deadline = options.lock_timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let result = match shared {
+            true => file.try_lock_shared(),
+            false => file.try_lock(),
+        };
+
+        match result {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) => match deadline {
+                Some(deadline) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                _ => return Err(Error::Locked),
+            },
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::delta;
+use crate::error::{Error, Result};
+use crate::persist::FileStamp;
+use crate::store::Store;
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// What [`Store::maintain`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// The combined size of the store file and its delta file before maintenance.
+    pub bytes_before: u64,
+
+    /// The size of the store file after maintenance.
+    pub bytes_after: u64,
+
+    /// How many tombstones were purged.
+    pub tombstones_purged: usize,
+}
+
+impl MaintenanceReport {
+    /// How many bytes of disk space maintenance freed, or 0 if the store grew.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// The combined size of the store file at `options.path` and its delta file, or 0 if neither
+    /// exists yet. Comparing it to the size a save would write, from
+    /// [`save_dry_run`](Self::save_dry_run), tells whether [`compact`](Self::compact) is worth it.
+    pub fn disk_size(&self) -> Result<u64> {
+        let path = &self.options.path;
+        Ok(file_size(path)? + file_size(&delta::delta_path(path))?)
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Runs routine maintenance in one call, e.g. from a scheduled job: purges tombstones older
+    /// than `tombstone_age`, releases unused memory, writes a fresh snapshot that folds in and
+    /// removes the delta file, and reopens the result to check that it holds exactly what was
+    /// saved, failing with `Error::InvalidFile` if it doesn't.
+    pub fn maintain(&mut self, tombstone_age: Duration) -> Result<MaintenanceReport> {
+        let bytes_before = self.disk_size()?;
+
+        let tombstones_purged = self.purge_tombstones(tombstone_age);
+        self.shrink_to_fit();
+        self.save()?;
+
+        let saved = Self::open_file(&self.options.path, self.options.clone())?;
+        if saved.content_hash()? != self.content_hash()? || saved.metadata != self.metadata {
+            return Err(Error::InvalidFile);
+        }
+
+        Ok(MaintenanceReport {
+            bytes_before,
+            bytes_after: file_size(&self.options.path)?,
+            tombstones_purged,
+        })
+    }
+}
+
+/// The size of the file at `path`, or 0 if there is none.
+fn file_size(path: &Path) -> io::Result<u64> {
+    Ok(FileStamp::of_path(path)?.map_or(0, |stamp| stamp.len))
+}
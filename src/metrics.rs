@@ -0,0 +1,121 @@
+use crate::store::Store;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Timings of one kind of operation, collected with `StoreOptions::collect_metrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationMetrics {
+    /// How many times the operation ran.
+    pub count: u64,
+
+    /// The combined duration of every run.
+    pub total: Duration,
+
+    /// The duration of the slowest run.
+    pub max: Duration,
+}
+
+impl OperationMetrics {
+    /// The average duration of a run, or zero if there were none.
+    pub fn average(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total.div_f64(count as f64),
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+}
+
+/// Timings of a store's operations, returned by [`Store::metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreMetrics {
+    /// [`Store::open`], counted once by the store it opened.
+    pub open: OperationMetrics,
+
+    /// [`Store::insert`], including insertions made by other methods through it.
+    pub insert: OperationMetrics,
+
+    /// [`Store::remove`], including removals of keys that weren't present.
+    pub remove: OperationMetrics,
+
+    /// Full and incremental saves.
+    pub save: OperationMetrics,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operation {
+    Open,
+    Insert,
+    Remove,
+    Save,
+}
+
+/// The metrics collected so far. They are updated by `save`, which only borrows the store, hence
+/// the mutex.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics(Mutex<StoreMetrics>);
+
+impl Metrics {
+    pub(crate) fn get(&self) -> StoreMetrics {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn record(&self, operation: Operation, elapsed: Duration) {
+        let mut metrics = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let metrics = match operation {
+            Operation::Open => &mut metrics.open,
+            Operation::Insert => &mut metrics.insert,
+            Operation::Remove => &mut metrics.remove,
+            Operation::Save => &mut metrics.save,
+        };
+        metrics.record(elapsed);
+    }
+
+    /// Starts timing `operation` if `enabled`, recording it once the returned timer is dropped.
+    /// When disabled, this costs nothing but the branch.
+    pub(crate) fn time(&self, enabled: bool, operation: Operation) -> Timer<'_> {
+        Timer {
+            metrics: self,
+            operation,
+            started: enabled.then(Instant::now),
+        }
+    }
+}
+
+impl Clone for Metrics {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.get()))
+    }
+}
+
+pub(crate) struct Timer<'a> {
+    metrics: &'a Metrics,
+    operation: Operation,
+    started: Option<Instant>,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        if let Some(started) = self.started {
+            self.metrics.record(self.operation, started.elapsed());
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// The timings collected since the store was created or opened, with
+    /// `StoreOptions::collect_metrics` enabled.
+    pub fn metrics(&self) -> StoreMetrics {
+        self.metrics.get()
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::{Error, Result};
+use crate::persist::{replace_file, temp_path};
+use crate::store::{Store, StoreOptions};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fmt;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+const MAGIC_ID: &[u8] = b"OGMS";
+const VERSION: u16 = 1;
+
+/// Several independent stores kept in a single file, each under its own name.
+/// Every section is a complete store with its own key and value types, encoded exactly as
+/// [`Store::save`] would write it, so sections that are never accessed are never decoded.
+///
+/// The file does not record the types of a section. Accessing a section with different types
+/// than it was saved with usually fails to decode, but types with the same encoding, such as
+/// `u32` and `u64`, are not told apart.
+pub struct MultiStore {
+    options: StoreOptions,
+    sections: BTreeMap<String, Section>,
+}
+
+enum Section {
+    /// A section as read from the file, and not yet accessed.
+    Encoded(Vec<u8>),
+    Decoded(Box<dyn AnySection>),
+}
+
+/// A store of any types that can be saved as a section.
+trait AnySection: Any + Send + Sync {
+    fn to_bytes(&self) -> Result<Vec<u8>>;
+}
+
+impl<K, V> AnySection for Store<K, V>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de> + Send + Sync + 'static,
+    V: Serialize + for<'de> Deserialize<'de> + Send + Sync + 'static,
+{
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        Store::to_bytes(self)
+    }
+}
+
+impl fmt::Debug for MultiStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiStore")
+            .field("options", &self.options)
+            .field("sections", &self.sections.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl MultiStore {
+    pub fn new(options: StoreOptions) -> Self {
+        Self {
+            options,
+            sections: BTreeMap::new(),
+        }
+    }
+
+    /// Opens the file at `options.path`, or creates an empty store if it doesn't exist.
+    /// Sections are only read into memory here, and decoded when first accessed.
+    pub fn open(options: StoreOptions) -> Result<Self> {
+        let file = match File::open(&options.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new(options)),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut magic_id = [0u8; 4];
+        reader.read_exact(&mut magic_id)?;
+        if magic_id != MAGIC_ID {
+            return Err(Error::InvalidFile);
+        }
+
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version > VERSION {
+            return Err(Error::FileTooNew {
+                file: version,
+                supported: VERSION,
+            });
+        }
+
+        let mut store = Self::new(options);
+        for _ in 0..reader.read_u32::<LittleEndian>()? {
+            let mut name = vec![0u8; reader.read_u16::<LittleEndian>()? as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| Error::InvalidFile)?;
+
+            let len = reader.read_u64::<LittleEndian>()?;
+            let mut bytes = Vec::new();
+            (&mut reader).take(len).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 != len {
+                return Err(Error::InvalidFile);
+            }
+
+            store.sections.insert(name, Section::Encoded(bytes));
+        }
+
+        Ok(store)
+    }
+
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+
+    /// The names of every section, in order.
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.sections.keys().map(String::as_str)
+    }
+
+    pub fn contains_section(&self, name: &str) -> bool {
+        self.sections.contains_key(name)
+    }
+
+    /// The section named `name`, decoding it on first access or creating it if it doesn't exist.
+    /// Fails with `Error::SectionType` if the section was already accessed with different types.
+    ///
+    /// Sections share this store's options, except that they have no path of their own: they are
+    /// saved together by [`save`](Self::save) rather than individually.
+    pub fn section<K, V>(&mut self, name: &str) -> Result<&mut Store<K, V>>
+    where
+        K: Eq + Hash + Serialize + for<'de> Deserialize<'de> + Send + Sync + 'static,
+        V: Serialize + for<'de> Deserialize<'de> + Send + Sync + 'static,
+    {
+        let options = StoreOptions {
+            path: PathBuf::new(),
+            ..self.options.clone()
+        };
+        let section = match self.sections.entry(name.to_owned()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Section::Decoded(Box::new(Store::<K, V>::new(
+                options.clone(),
+            )))),
+        };
+
+        if let Section::Encoded(bytes) = section {
+            let store = Store::<K, V>::from_bytes(bytes, options)?;
+            *section = Section::Decoded(Box::new(store));
+        }
+
+        let Section::Decoded(store) = section else {
+            unreachable!("the section was decoded above");
+        };
+        let store: &mut dyn Any = store.as_mut();
+        store
+            .downcast_mut()
+            .ok_or_else(|| Error::SectionType(name.to_owned()))
+    }
+
+    /// Removes a section, returning whether it existed.
+    pub fn remove_section(&mut self, name: &str) -> bool {
+        self.sections.remove(name).is_some()
+    }
+
+    /// Writes every section to `options.path`, replacing the file atomically like `Store::save`.
+    pub fn save(&self) -> Result<()> {
+        if self.options.create_dirs
+            && let Some(parent) = self.options.path.parent()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = temp_path(&self.options.path);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(MAGIC_ID)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        writer.write_u32::<LittleEndian>(self.sections.len() as u32)?;
+        for (name, section) in &self.sections {
+            let encoded;
+            let bytes = match section {
+                Section::Encoded(bytes) => bytes,
+                Section::Decoded(store) => {
+                    encoded = store.to_bytes()?;
+                    &encoded
+                }
+            };
+
+            let name_len = u16::try_from(name.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "section name is too long")
+            })?;
+            writer.write_u16::<LittleEndian>(name_len)?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_u64::<LittleEndian>(bytes.len() as u64)?;
+            writer.write_all(bytes)?;
+        }
+
+        let file = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        drop(file);
+
+        replace_file(&temp_path, &self.options.path)?;
+        Ok(())
+    }
+}
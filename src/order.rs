@@ -0,0 +1,54 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Remembers the order in which keys were first inserted, for `StoreOptions::stable_order`.
+#[derive(Debug, Clone)]
+pub(crate) struct InsertionOrder<K> {
+    sequence: HashMap<K, u64>,
+    next: u64,
+}
+
+impl<K> InsertionOrder<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            sequence: HashMap::new(),
+            next: 0,
+        }
+    }
+}
+
+impl<K: Eq + Hash> InsertionOrder<K> {
+    /// Records `key` as inserted now, unless it already has a position.
+    pub(crate) fn record(&mut self, key: &K)
+    where
+        K: Clone,
+    {
+        if !self.sequence.contains_key(key) {
+            self.sequence.insert(key.clone(), self.next);
+            self.next += 1;
+        }
+    }
+
+    pub(crate) fn forget<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.sequence.remove(key);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.sequence.clear();
+    }
+
+    pub(crate) fn shrink_to(&mut self, min_capacity: usize) {
+        self.sequence.shrink_to(min_capacity);
+    }
+
+    /// The position of `key`, or `None` if it was never recorded,
+    /// e.g. because it was loaded from disk or inserted through `Store::map_mut`.
+    pub(crate) fn position(&self, key: &K) -> Option<u64> {
+        self.sequence.get(key).copied()
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::Result;
+use crate::persist::write_entries_file;
+use crate::store::{Store, StoreOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+
+/// A store backed by a `BTreeMap`, keeping its entries sorted by key.
+///
+/// It dereferences to the map, so range queries such as `range`, `first_key_value`, and
+/// `last_key_value` work directly on it, e.g. to fetch every entry in a window of timestamps.
+///
+/// The file format is that of [`Store`], so either can open the other's files. Opening goes
+/// through `Store::open`, with all of its options, but saving only writes the entries, honoring
+/// the path, compression, backup, and type tag options. For tombstones, checksums, incremental
+/// saves, and the rest, convert to and from a `Store` instead.
+#[derive(Debug, Clone)]
+pub struct OrderedStore<K, V> {
+    map: BTreeMap<K, V>,
+    options: StoreOptions,
+}
+
+impl<K, V> OrderedStore<K, V> {
+    pub fn new(options: StoreOptions) -> Self {
+        Self {
+            map: BTreeMap::new(),
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+}
+
+impl<K, V> OrderedStore<K, V>
+where
+    K: Ord + Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+{
+    /// Opens the store at `options.path` like [`Store::open`], sorting its entries.
+    pub fn open(options: StoreOptions) -> Result<Self> {
+        Ok(Store::<K, V>::open(options)?.into())
+    }
+
+    /// Atomically replaces the file at `options.path` with the entries, in key order.
+    pub fn save(&self) -> Result<()> {
+        write_entries_file(&self.options, self.map.len() as u64, |enc| {
+            for (key, value) in &self.map {
+                rmp_serde::encode::write(enc, key)?;
+                rmp_serde::encode::write(enc, value)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<K, V> Deref for OrderedStore<K, V> {
+    type Target = BTreeMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K, V> DerefMut for OrderedStore<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+impl<K, V, S> From<Store<K, V, S>> for OrderedStore<K, V>
+where
+    K: Ord + Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Sorts the entries of `store`, keeping its options.
+    fn from(mut store: Store<K, V, S>) -> Self {
+        Self {
+            options: std::mem::take(&mut store.options),
+            map: store.into_map().into_iter().collect(),
+        }
+    }
+}
+
+impl<K, V, S> From<OrderedStore<K, V>> for Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Moves the entries of `store` into a hash map, keeping its options.
+    fn from(store: OrderedStore<K, V>) -> Self {
+        let mut unordered = Store::new(store.options);
+        unordered.map = store.map.into_iter().collect();
+        unordered
+    }
+}
@@ -0,0 +1,1811 @@
+use crate::backup;
+use crate::checksum::Digest;
+use crate::delta;
+use crate::error::{Error, RecoveryWarning, Result};
+use crate::hash::{Blake3Reader, Blake3Writer};
+use crate::info::StoreInfo;
+use crate::lock::FileLock;
+use crate::metrics::Operation;
+use crate::store::{AccessMode, CompressionLevel, OnDuplicate, Store, StoreOptions};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hashbrown::HashMap;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use zstd::{Decoder, Encoder};
+
+const MAGIC_ID: &[u8] = b"OGMA";
+const VERSION: u16 = 6;
+
+/// The oldest version this library can read. Version 3 has no flags and always holds a compressed
+/// payload, version 4 has no checksum trailer, and version 5 has no entry count or compression
+/// level after the flags.
+const OLDEST_VERSION: u16 = 3;
+
+/// Set when the payload is zstd-compressed. Otherwise it is stored as raw MessagePack.
+const FLAG_COMPRESSED: u16 = 1 << 0;
+
+/// Set when the payload carries a map of deleted keys to their deletion times after the entries.
+const FLAG_TOMBSTONES: u16 = 1 << 1;
+
+/// Set when a length-prefixed block of application metadata follows the header.
+const FLAG_METADATA: u16 = 1 << 2;
+
+/// Set when the payload carries a map of every key to a checksum of its value after the entries.
+const FLAG_CHECKSUMS: u16 = 1 << 3;
+
+/// Set when the id sequence of `Store::next_id` follows the metadata block.
+const FLAG_SEQUENCE: u16 = 1 << 4;
+
+/// Set when the type tag of `StoreOptions::type_tag` follows the sequence.
+const FLAG_TYPE_TAG: u16 = 1 << 5;
+
+/// Set when the payload carries a map of keys inserted with a TTL to their expiry times.
+const FLAG_EXPIRIES: u16 = 1 << 6;
+
+/// Set when the payload is compressed with a dictionary whose hash follows the type tag.
+const FLAG_DICTIONARY: u16 = 1 << 7;
+
+/// Every flag this version of the library understands.
+const KNOWN_FLAGS: u16 = FLAG_COMPRESSED
+    | FLAG_TOMBSTONES
+    | FLAG_METADATA
+    | FLAG_CHECKSUMS
+    | FLAG_SEQUENCE
+    | FLAG_TYPE_TAG
+    | FLAG_EXPIRIES
+    | FLAG_DICTIONARY;
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Opens the store at `options.path`, or creates an empty store if there is no file there.
+    /// Fails with `Error::NotAFile` if the path exists but is a directory or other non-file, rather
+    /// than treating it as a store that hasn't been created yet.
+    pub fn open(options: StoreOptions) -> Result<Self> {
+        if !is_store_file(&options.path)? {
+            let store = Self::new(options);
+            store.flush_on_drop();
+            Ok(store)
+        } else {
+            let started = options.collect_metrics.then(Instant::now);
+            let path = options.path.clone();
+            let lock = FileLock::for_open(&options)?;
+            let mut store = Self::open_file(&path, options)?;
+            store.lock = lock;
+
+            // A reader can't save while it holds its shared lock.
+            if store.options.access_mode != Some(AccessMode::Read) && store.needs_compaction()? {
+                store.compact()?;
+            }
+
+            if let Some(started) = started {
+                store.metrics.record(Operation::Open, started.elapsed());
+            }
+            store.flush_on_drop();
+            Ok(store)
+        }
+    }
+
+    /// Like [`open`](Self::open), but when there is no file at `options.path`, the new store is
+    /// filled with the entries returned by `init`, e.g. to seed defaults on first run.
+    /// The seeded store is not written until it is saved.
+    pub fn open_or_init<F>(options: StoreOptions, init: F) -> Result<Self>
+    where
+        F: FnOnce() -> HashMap<K, V, S>,
+    {
+        if is_store_file(&options.path)? {
+            return Self::open(options);
+        }
+
+        let mut store = Self::new(options);
+        store.map = init();
+        store.revision.bump();
+        store.flush_on_drop();
+        Ok(store)
+    }
+
+    /// Like [`open`](Self::open), but runs `validate` on the opened store, e.g. to check invariants
+    /// that span entries, and fails with `Error::Validation` holding its error's message if it
+    /// fails. A store that doesn't exist yet is validated too, as an empty store.
+    /// The options can't carry the hook themselves since they aren't specific to `K` and `V`.
+    pub fn open_validated<F, E>(options: StoreOptions, validate: F) -> Result<Self>
+    where
+        F: FnOnce(&Self) -> core::result::Result<(), E>,
+        E: std::fmt::Display,
+    {
+        let store = Self::open(options)?;
+        validate(&store).map_err(|e| Error::Validation(e.to_string()))?;
+        Ok(store)
+    }
+
+    /// Reads the store at `path`, which need not be `options.path`.
+    pub(crate) fn open_file(path: &Path, options: StoreOptions) -> Result<Self> {
+        let file = File::open(path)?;
+        let stamp = FileStamp::of(&file)?;
+        let mut store = Self::read_from(file, options)?;
+        store.apply_delta(path, stamp)?;
+        store.disk_stamp.set(Some(stamp));
+        Ok(store)
+    }
+
+    /// Recovers as much of a damaged store file as possible.
+    /// The header must be intact, but a truncated or corrupted body is decompressed and decoded
+    /// entry by entry, skipping entries that fail to decode and stopping at the first point where
+    /// the data can no longer be followed. Returns the recovered store alongside the number of
+    /// entries that were dropped.
+    pub fn repair(options: StoreOptions) -> Result<(Self, usize)> {
+        Self::salvage(options, &mut Vec::new())
+    }
+
+    /// Opens the store, falling back to [`repair`](Self::repair)'s entry-by-entry salvage if the
+    /// file is damaged, and reports what had to be skipped instead of failing outright.
+    /// The warnings are empty when the file opened normally. A file that can't be opened or whose
+    /// header is unreadable still fails, since nothing identifies what it holds. Pending delta
+    /// layers are only applied when the file opens normally.
+    pub fn open_recover(options: StoreOptions) -> Result<(Self, Vec<RecoveryWarning>)> {
+        let error = match Self::open(options.clone()) {
+            Ok(store) => return Ok((store, Vec::new())),
+            Err(error) => error,
+        };
+
+        // Salvaging opens the file and reads the header again, failing the same way if either
+        // can't be done, while a truncated body surfaces as an I/O error that is worth salvaging.
+        let mut warnings = vec![RecoveryWarning::OpenFailed(error)];
+        let (store, _) = Self::salvage(options, &mut warnings)?;
+        Ok((store, warnings))
+    }
+
+    /// Decodes every entry of the file that can still be read, recording what was skipped.
+    /// Returns the store alongside the number of entries that were dropped.
+    fn salvage(
+        options: StoreOptions,
+        warnings: &mut Vec<RecoveryWarning>,
+    ) -> Result<(Self, usize)> {
+        let file = BufReader::new(File::open(&options.path)?);
+        let mut decoder = payload_reader(file, &options)?;
+        let metadata = decoder.metadata.take();
+
+        // Keep everything that decompresses cleanly, even if the stream ends in garbage.
+        let mut payload = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            match decoder.read(&mut buf) {
+                Ok(0) => {
+                    // A checksum that was cut off doesn't affect the entries, but it still means
+                    // the file was truncated.
+                    if let Err(Error::Io(_)) = decoder.finish() {
+                        warnings.push(RecoveryWarning::Truncated {
+                            decompressed: payload.len() as u64,
+                        });
+                    }
+                    break;
+                }
+                Ok(n) => payload.extend_from_slice(&buf[..n]),
+                Err(_) => {
+                    warnings.push(RecoveryWarning::Truncated {
+                        decompressed: payload.len() as u64,
+                    });
+                    break;
+                }
+            }
+        }
+        decoder.check_limit(Ok(()))?;
+
+        let mut cursor = Cursor::new(payload.as_slice());
+        rmp::decode::read_array_len(&mut cursor).map_err(rmp_serde::decode::Error::from)?;
+        let len = rmp::decode::read_map_len(&mut cursor).map_err(rmp_serde::decode::Error::from)?;
+
+        let mut store = Self::new(options);
+        store.metadata = metadata;
+        store.sequence = decoder.sequence;
+        for i in 0..len {
+            let start = cursor.position();
+            let decoded = decode_or_skip::<K>(&mut cursor).and_then(|key| {
+                let key_end = cursor.position();
+                Some((key, key_end, decode_or_skip::<V>(&mut cursor)?))
+            });
+            let Some((key, key_end, value)) = decoded else {
+                warnings.push(RecoveryWarning::Unreadable {
+                    offset: start,
+                    dropped: (len - i) as usize,
+                });
+                break;
+            };
+
+            if let (Some(key), Some(value)) = (key, value) {
+                store.map.insert(key, value);
+            } else {
+                warnings.push(RecoveryWarning::SkippedEntry {
+                    range: start..cursor.position(),
+                    key: describe_key(&payload[start as usize..key_end as usize]),
+                });
+            }
+        }
+
+        // The recovered store no longer matches the damaged file.
+        store.revision.bump();
+        let dropped = len as usize - store.map.len();
+        Ok((store, dropped))
+    }
+
+    /// Decodes a store from a buffer produced by [`to_bytes`](Self::to_bytes).
+    /// The buffer holds exactly what [`save`](Self::save) would write to disk.
+    pub fn from_bytes(bytes: &[u8], options: StoreOptions) -> Result<Self> {
+        Self::decode(bytes, options)
+    }
+
+    /// Decodes a store from a buffer produced by [`to_bare_bytes`](Self::to_bare_bytes).
+    /// Without the magic ID and version nothing identifies the buffer as a store, so the caller is
+    /// responsible for only passing buffers that hold one.
+    pub fn from_bare_bytes(bytes: &[u8], options: StoreOptions) -> Result<Self> {
+        let payload = bare_payload_reader(bytes, &options)?;
+        let mut store = Self::new(options);
+        store.read_into(payload)?;
+        Ok(store)
+    }
+
+    /// Decodes a store from a reader positioned at the start of one, such as a member of a larger
+    /// archive. Only the store's own bytes are consumed, so the reader is left positioned right
+    /// after it. Pass the same `&mut BufReader` on to keep reading whatever follows, since the
+    /// buffer may already hold those bytes.
+    pub fn from_reader<R: BufRead>(reader: R, options: StoreOptions) -> Result<Self> {
+        Self::decode(reader, options)
+    }
+
+    /// Decodes a store written by [`write_to`](Self::write_to) or [`save`](Self::save) from any
+    /// reader, such as a network stream. The reader is buffered internally, so it may be consumed
+    /// past the end of the store; use [`from_reader`](Self::from_reader) to keep reading after it.
+    pub fn read_from<R: Read>(reader: R, options: StoreOptions) -> Result<Self> {
+        Self::decode(BufReader::new(reader), options)
+    }
+
+    /// Reads only the keys of the store at `options.path`, skipping over every value without
+    /// decoding it. This is much cheaper than [`open`](Self::open) when only membership or the
+    /// key set is needed. A missing file yields an empty set, and a directory is an error, as with
+    /// `open`.
+    pub fn load_keys(options: &StoreOptions) -> Result<HashSet<K>> {
+        if !is_store_file(&options.path)? {
+            return Ok(HashSet::new());
+        }
+
+        let file = File::open(&options.path)?;
+        let stamp = FileStamp::of(&file)?;
+        let mut payload = payload_reader(BufReader::new(file), options)?;
+        let keys = rmp_serde::decode::from_read(&mut payload).map_err(Error::from);
+        let keys: KeysOnly<K> = payload.check_limit(keys)?;
+
+        let mut keys = keys.map.0;
+        delta::apply_to_keys(
+            &mut keys,
+            &options.path,
+            stamp,
+            options.max_decompressed_bytes,
+        )?;
+        Ok(keys)
+    }
+
+    /// Counts the entries of the store at `options.path` without decoding any of them, e.g. for a
+    /// health check that the store opens and isn't empty. A missing file has no entries.
+    pub fn count_entries(options: &StoreOptions) -> Result<usize> {
+        if !is_store_file(&options.path)? {
+            return Ok(0);
+        }
+
+        let file = File::open(&options.path)?;
+        let stamp = FileStamp::of(&file)?;
+        if delta::exists(&options.path, stamp)? {
+            // Layers may add or remove keys that are already present, so they have to be followed.
+            return Ok(Self::load_keys(options)?.len());
+        }
+
+        // The map's header records its length, so none of the entries need to be read.
+        let mut reader = sections_reader(BufReader::new(file), options)?;
+        let len = rmp::decode::read_map_len(&mut reader).map_err(rmp_serde::decode::Error::from)?;
+        Ok(len as usize)
+    }
+
+    /// Decodes the entries of the store at `options.path` one at a time, passing each to `f` and
+    /// dropping it before the next is decoded, so a store larger than memory can be processed.
+    /// Stops at and returns the first error, including one returned by `f`. If the store has
+    /// delta layers, they may supersede any entry, so the whole store is opened first instead.
+    /// A missing file has no entries.
+    pub fn for_each_entry<F>(options: &StoreOptions, mut f: F) -> Result<()>
+    where
+        F: FnMut(K, V) -> Result<()>,
+    {
+        if !is_store_file(&options.path)? {
+            return Ok(());
+        }
+
+        let file = File::open(&options.path)?;
+        let stamp = FileStamp::of(&file)?;
+        if delta::exists(&options.path, stamp)? {
+            let store = Self::open_file(&options.path, options.clone())?;
+            return store
+                .into_map()
+                .into_iter()
+                .try_for_each(|(key, value)| f(key, value));
+        }
+
+        let mut reader = sections_reader(BufReader::new(file), options)?;
+        let result = (|| -> Result<()> {
+            for entry in Entries::new(&mut reader)? {
+                let (key, value) = entry?;
+                f(key, value)?;
+            }
+            Ok(())
+        })();
+
+        reader.inner.check_limit(result)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_with_level(self.options.compression_level)
+    }
+
+    /// Saves exactly like [`save`](Self::save), but compresses with `level` for this call only
+    /// instead of `options.compression_level`, e.g. for a one-off archival save.
+    pub fn save_with_level(&self, level: CompressionLevel) -> Result<()> {
+        let _timer = self
+            .metrics
+            .time(self.options.collect_metrics, Operation::Save);
+        let _lock = self.lock_for_write()?;
+        let staged = self.stage(level)?;
+        if let Err(e) = self.check_unchanged() {
+            let _ = std::fs::remove_file(&staged.temp_path);
+            return Err(e);
+        }
+
+        self.commit(staged)?;
+        self.flush_on_drop();
+        Ok(())
+    }
+
+    /// Saves the store if it changed since it was opened or last saved, appending to the delta
+    /// file with [`save_incremental`](Self::save_incremental) when `options.incremental` is set.
+    pub fn flush(&self) -> Result<()> {
+        match (self.needs_save(), self.options.incremental) {
+            (false, _) => Ok(()),
+            (true, true) => self.save_incremental(),
+            (true, false) => self.save(),
+        }
+    }
+
+    /// Like [`insert`](Self::insert), but flushes the change to disk when `options.autosave` is
+    /// enabled. The entry stays inserted if the flush fails.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>>
+    where
+        K: Clone,
+    {
+        let old = self.insert(key, value);
+        self.autosave()?;
+        Ok(old)
+    }
+
+    /// Like [`remove`](Self::remove), but flushes the change to disk when `options.autosave` is
+    /// enabled. The entry stays removed if the flush fails.
+    pub fn try_remove<Q>(&mut self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        let value = self.remove(key);
+        self.autosave()?;
+        Ok(value)
+    }
+
+    /// Like [`clear`](Self::clear), but flushes the change to disk when `options.autosave` is
+    /// enabled.
+    pub fn try_clear(&mut self) -> Result<()>
+    where
+        K: Clone,
+    {
+        self.clear();
+        self.autosave()
+    }
+
+    fn autosave(&self) -> Result<()> {
+        match self.options.autosave {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+
+    /// Lets the store flush itself when it's dropped, which it does if `options.autosave` is
+    /// enabled and it has unsaved changes by then.
+    pub(crate) fn flush_on_drop(&self) {
+        self.drop_flush.set(Some(Self::flush));
+    }
+
+    /// Writes the store to its temporary file, the first step of a save.
+    /// The temporary file is removed if writing it fails.
+    pub(crate) fn stage(&self, level: CompressionLevel) -> Result<StagedSave> {
+        check_path(&self.options.path)?;
+        if self.options.create_dirs
+            && let Some(parent) = self.options.path.parent()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = temp_path(&self.options.path);
+        let write = || -> Result<FileStamp> {
+            let file = File::create(&temp_path)?;
+            let reserved = match self.disk_stamp.get() {
+                Some(stamp) if self.options.preallocate => stamp.len,
+                _ => 0,
+            };
+            if reserved > 0 {
+                file.set_len(reserved)?;
+            }
+
+            // An uncompressed payload is written in many small pieces, so it's buffered here.
+            let file = self.write_with_level(BufWriter::new(file), level)?;
+            let mut file = file.into_inner().map_err(io::Error::from)?;
+            if reserved > 0 {
+                let written = file.stream_position()?;
+                file.set_len(written)?;
+            }
+            file.sync_all()?;
+            Ok(FileStamp::of(&file)?)
+        };
+
+        match write() {
+            Ok(stamp) => Ok(StagedSave { temp_path, stamp }),
+            Err(e) => {
+                // The write error is more useful than any failure to clean up after it.
+                let _ = std::fs::remove_file(&temp_path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Fails with `Error::ConcurrentModification` if `check_before_overwrite` is set and the file
+    /// was modified since the store was opened or last saved.
+    pub(crate) fn check_unchanged(&self) -> Result<()> {
+        if self.options.check_before_overwrite
+            && FileStamp::of_path(&self.options.path)? != self.disk_stamp.get()
+        {
+            return Err(Error::ConcurrentModification);
+        }
+
+        Ok(())
+    }
+
+    /// Moves a staged save into place, the last step of a save.
+    pub(crate) fn commit(&self, staged: StagedSave) -> Result<()> {
+        backup::rotate(&self.options.path, self.options.backups)?;
+        replace_file(&staged.temp_path, &self.options.path)?;
+        self.disk_stamp.set(Some(staged.stamp));
+        self.revision.mark_saved();
+        self.changes.take();
+        delta::remove(&self.options.path)?;
+
+        Ok(())
+    }
+
+    /// Encodes the store into an in-memory buffer with the same header, compression, and
+    /// serialization that [`save`](Self::save) writes to disk.
+    /// This is useful where there is no filesystem, such as WASM, or to persist the store elsewhere.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.write_with_level(Vec::new(), self.options.compression_level)
+    }
+
+    /// Writes the store to `writer` in the same format that [`save`](Self::save) writes to disk.
+    /// Unlike `save`, there is no temporary file to rename into place, so if writing fails partway
+    /// the writer is left holding a partial store; making the result durable is up to the caller.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        self.write_with_level(writer, self.options.compression_level)?;
+        Ok(())
+    }
+
+    /// Like [`to_bytes`](Self::to_bytes), but leaves out the magic ID and version, for embedding
+    /// the store in a container with framing of its own. The rest of the header is kept since it
+    /// describes how the payload is stored. Read it back with
+    /// [`from_bare_bytes`](Self::from_bare_bytes) using the same version of the library.
+    pub fn to_bare_bytes(&self) -> Result<Vec<u8>> {
+        self.write_bare(Vec::new(), self.options.compression_level)
+    }
+
+    /// Computes a fingerprint of the store's contents.
+    /// The hash depends only on the entries, not on their iteration order, the compression level,
+    /// or anything else about how the store is saved, so two stores with equal contents always match.
+    pub fn content_hash(&self) -> Result<blake3::Hash> {
+        self.hash_entries(Blake3Writer::new(io::sink()))
+    }
+
+    /// Like [`content_hash`](Self::content_hash), but keyed so that different keys produce unrelated
+    /// fingerprints for the same contents, e.g. to namespace tenants sharing a cache index.
+    pub fn content_hash_keyed(&self, key: [u8; 32]) -> Result<blake3::Hash> {
+        self.hash_entries(Blake3Writer::new_keyed(io::sink(), &key))
+    }
+
+    /// Whether saving would write different entries or metadata than the file at `options.path`
+    /// holds, including any delta layers. Unlike [`needs_save`](Self::needs_save), this notices
+    /// changes that were later reverted, but it reads and decodes the whole file to compare
+    /// content hashes. A missing file differs unless the store is empty and has no metadata.
+    pub fn differs_from_disk(&self) -> Result<bool> {
+        if !is_store_file(&self.options.path)? {
+            return Ok(!self.map.is_empty() || self.metadata.is_some());
+        }
+
+        let on_disk = Self::open_file(&self.options.path, self.options.clone())?;
+        Ok(on_disk.metadata != self.metadata || on_disk.content_hash()? != self.content_hash()?)
+    }
+
+    /// Whether the store files at `a` and `b` hold the same entries and metadata, including any
+    /// delta layers, regardless of how each was saved, e.g. to check a rebuilt store against a
+    /// reference file. Like [`differs_from_disk`](Self::differs_from_disk), both files are decoded
+    /// in full to compare content hashes.
+    pub fn files_equivalent(a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<bool> {
+        let open = |path: &Path| Self::open_file(path, StoreOptions::new(path));
+        let (a, b) = (open(a.as_ref())?, open(b.as_ref())?);
+        Ok(a.metadata == b.metadata && a.content_hash()? == b.content_hash()?)
+    }
+
+    fn hash_entries(&self, mut writer: Blake3Writer<io::Sink>) -> Result<blake3::Hash> {
+        // Entries are hashed in the order of their encoded bytes since map order is unspecified.
+        let mut entries = self
+            .map
+            .iter()
+            .map(|entry| rmp_serde::encode::to_vec(&entry))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        entries.sort_unstable();
+
+        for entry in &entries {
+            writer.write_all(entry)?;
+        }
+
+        Ok(writer.finalize().1)
+    }
+
+    pub(crate) fn write_with_level<W: Write>(
+        &self,
+        mut writer: W,
+        level: CompressionLevel,
+    ) -> Result<W> {
+        writer.write_all(MAGIC_ID)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        self.write_bare(writer, level)
+    }
+
+    /// Writes everything after the magic ID and version, followed by the checksum trailer.
+    fn write_bare<W: Write>(&self, writer: W, level: CompressionLevel) -> Result<W> {
+        let mut writer = Blake3Writer::new(writer);
+        // Only buffer the payload up front when its size decides whether to compress it.
+        let payload = match self.options.min_compress_bytes {
+            _ if level.is_none() => None,
+            0 => None,
+            _ => {
+                let mut payload = Vec::new();
+                self.write_payload(&mut payload)?;
+                Some(payload)
+            }
+        };
+
+        let compress = !level.is_none()
+            && !matches!(&payload, Some(payload) if payload.len() < self.options.min_compress_bytes);
+        let dictionary = self.options.dictionary.as_deref().filter(|_| compress);
+        let mut flags = self.payload_flags();
+        if compress {
+            flags |= FLAG_COMPRESSED;
+        }
+        if dictionary.is_some() {
+            flags |= FLAG_DICTIONARY;
+        }
+        writer.write_u16::<LittleEndian>(flags)?;
+        writer.write_u64::<LittleEndian>(self.map.len() as u64)?;
+        writer.write_i32::<LittleEndian>(level.level())?;
+
+        if let Some(metadata) = &self.metadata {
+            writer.write_u32::<LittleEndian>(metadata.len() as u32)?;
+            writer.write_all(metadata)?;
+        }
+        if self.sequence != 0 {
+            writer.write_u64::<LittleEndian>(self.sequence)?;
+        }
+        if let Some(tag) = self.options.type_tag {
+            writer.write_u32::<LittleEndian>(tag)?;
+        }
+        if let Some(dictionary) = dictionary {
+            writer.write_all(blake3::hash(dictionary).as_bytes())?;
+        }
+
+        let writer = match payload {
+            Some(payload) if !compress => {
+                writer.write_all(&payload)?;
+                writer
+            }
+            None if !compress => {
+                self.write_payload(&mut writer)?;
+                writer
+            }
+            payload => {
+                let mut enc = match dictionary {
+                    Some(dictionary) => {
+                        Encoder::with_dictionary(writer, level.level(), dictionary)?
+                    }
+                    None => Encoder::new(writer, level.level())?,
+                };
+                if self.options.compression_threads > 1 {
+                    enc.multithread(self.options.compression_threads)?;
+                }
+
+                match payload {
+                    Some(payload) => enc.write_all(&payload)?,
+                    None => self.write_payload(&mut enc)?,
+                }
+
+                enc.finish()?
+            }
+        };
+
+        Ok(write_trailer(writer)?)
+    }
+
+    /// The flags describing which optional sections the file carries.
+    fn payload_flags(&self) -> u16 {
+        let mut flags = 0;
+        if !self.tombstones.is_empty() {
+            flags |= FLAG_TOMBSTONES;
+        }
+        if self.metadata.is_some() {
+            flags |= FLAG_METADATA;
+        }
+        if self.options.entry_checksums {
+            flags |= FLAG_CHECKSUMS;
+        }
+        if self.sequence != 0 {
+            flags |= FLAG_SEQUENCE;
+        }
+        if self.options.type_tag.is_some() {
+            flags |= FLAG_TYPE_TAG;
+        }
+        if !self.expiries.is_empty() {
+            flags |= FLAG_EXPIRIES;
+        }
+
+        flags
+    }
+
+    /// Writes the serialized store, with its entries in insertion order if `stable_order` is set.
+    /// The payload is an array holding the map of entries followed by any optional sections, so a
+    /// store without optional sections matches the layout `rmp_serde` produces for `Store`.
+    pub(crate) fn write_payload<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let flags = self.payload_flags();
+        let mut serializer = rmp_serde::Serializer::new(writer);
+        rmp::encode::write_array_len(serializer.get_mut(), section_count(flags))
+            .map_err(rmp_serde::encode::Error::from)?;
+
+        if self.options.stable_order {
+            let mut entries = Vec::with_capacity(self.map.len());
+            for (key, value) in self.map.iter() {
+                // Keys without a position are tied, so break ties by their encoding to stay deterministic.
+                let (position, encoded) = match self.insertion_order.position(key) {
+                    Some(position) => (Some(position), Vec::new()),
+                    None => (None, rmp_serde::encode::to_vec(key)?),
+                };
+
+                entries.push((position, encoded, key, value));
+            }
+            entries.sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+            rmp::encode::write_map_len(serializer.get_mut(), entries.len() as u32)
+                .map_err(rmp_serde::encode::Error::from)?;
+            for (_, _, key, value) in entries {
+                key.serialize(&mut serializer)?;
+                value.serialize(&mut serializer)?;
+            }
+        } else {
+            self.map.serialize(&mut serializer)?;
+        }
+
+        if flags & FLAG_TOMBSTONES != 0 {
+            self.tombstones.serialize(&mut serializer)?;
+        }
+
+        if flags & FLAG_CHECKSUMS != 0 {
+            rmp::encode::write_map_len(serializer.get_mut(), self.map.len() as u32)
+                .map_err(rmp_serde::encode::Error::from)?;
+            for (key, value) in self.map.iter() {
+                key.serialize(&mut serializer)?;
+                Digest::of(value)?.serialize(&mut serializer)?;
+            }
+        }
+
+        if flags & FLAG_EXPIRIES != 0 {
+            self.expiries.serialize(&mut serializer)?;
+        }
+
+        Ok(())
+    }
+
+    fn decode<R: BufRead>(reader: R, options: StoreOptions) -> Result<Self> {
+        let payload = payload_reader(reader, &options)?;
+        let mut store = Self::new(options);
+        store.read_into(payload)?;
+        Ok(store)
+    }
+
+    /// Decodes a store's payload into this one, replacing its contents but keeping its allocations.
+    fn read_into<R: BufRead>(&mut self, payload: Payload<R>) -> Result<()> {
+        let mut reader = sections_of(payload)?;
+        let flags = reader.inner.flags;
+        if reader.inner.version < VERSION {
+            // Saving migrates the file to the current version.
+            self.revision.bump();
+        }
+
+        self.metadata = reader.inner.metadata.take();
+        self.sequence = reader.inner.sequence;
+        self.map.clear();
+        self.tombstones.clear();
+        self.checksums.clear();
+        self.expiries.clear();
+        let result = (|| -> Result<()> {
+            read_entries_into(&mut reader, &mut self.map)?;
+            if flags & FLAG_TOMBSTONES != 0 {
+                self.tombstones =
+                    HashMap::deserialize(&mut rmp_serde::Deserializer::new(&mut reader))?;
+            }
+            if flags & FLAG_CHECKSUMS != 0 {
+                self.checksums =
+                    HashMap::deserialize(&mut rmp_serde::Deserializer::new(&mut reader))?;
+            }
+            if flags & FLAG_EXPIRIES != 0 {
+                self.expiries =
+                    HashMap::deserialize(&mut rmp_serde::Deserializer::new(&mut reader))?;
+            }
+
+            reader.inner.finish()
+        })();
+
+        reader.inner.check_limit(result)
+    }
+
+    /// Replaces the store's contents with the file at `options.path`, as if it were opened again,
+    /// but refills the existing map instead of allocating a new one, e.g. for a reload loop driven
+    /// by [`watch`](Self::watch). The options are kept and the store no longer needs saving.
+    /// A missing file leaves the store empty. On error, the store may hold only part of the file.
+    pub fn reload(&mut self) -> Result<()> {
+        self.insertion_order.clear();
+        self.access.clear();
+        self.indexes.invalidate_all();
+        self.changes.take();
+        self.revision.bump();
+        if !is_store_file(&self.options.path)? {
+            self.map.clear();
+            self.tombstones.clear();
+            self.checksums.clear();
+            self.expiries.clear();
+            self.metadata = None;
+            self.sequence = 0;
+            self.disk_stamp.set(None);
+        } else {
+            let path = self.options.path.clone();
+            let file = File::open(&path)?;
+            let stamp = FileStamp::of(&file)?;
+            self.read_into(payload_reader(BufReader::new(file), &self.options)?)?;
+            self.apply_delta(&path, stamp)?;
+            self.disk_stamp.set(Some(stamp));
+        }
+
+        self.revision.mark_saved();
+        Ok(())
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Inserts every entry of the store at `options.path` into this one, handling keys present in
+    /// both as `on_duplicate` says, and returns how many entries were inserted. Entries are decoded
+    /// and inserted one at a time, so the other store is never held in memory as a whole. On
+    /// error, the entries merged so far are kept. A missing file merges nothing.
+    pub fn merge_file(
+        &mut self,
+        options: &StoreOptions,
+        on_duplicate: OnDuplicate,
+    ) -> Result<usize> {
+        if !is_store_file(&options.path)? {
+            return Ok(0);
+        }
+
+        let file = File::open(&options.path)?;
+        let stamp = FileStamp::of(&file)?;
+        if delta::exists(&options.path, stamp)? {
+            // Entries in the snapshot may be superseded by its delta layers, so apply them first.
+            let other = Self::open_file(&options.path, options.clone())?;
+            return self.insert_all(other.into_map(), on_duplicate);
+        }
+
+        let mut reader = sections_reader(BufReader::new(file), options)?;
+        let mut merged = 0;
+        let result = (|| -> Result<()> {
+            for entry in Entries::new(&mut reader)? {
+                let (key, value) = entry?;
+                merged += self.insert_with(key, value, on_duplicate)? as usize;
+            }
+            Ok(())
+        })();
+
+        reader.inner.check_limit(result)?;
+        Ok(merged)
+    }
+
+    /// Inserts every entry of `entries` with [`insert`](Self::insert), handling keys that are
+    /// already present, including those repeated within `entries`, as `on_duplicate` says.
+    /// Returns how many entries were inserted. On error, the entries inserted so far are kept.
+    pub fn insert_all<I>(&mut self, entries: I, on_duplicate: OnDuplicate) -> Result<usize>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut inserted = 0;
+        for (key, value) in entries {
+            inserted += self.insert_with(key, value, on_duplicate)? as usize;
+        }
+
+        Ok(inserted)
+    }
+
+    /// Moves every entry of `other` into this store, handling keys present in both as
+    /// `on_duplicate` says, and returns how many entries were inserted. Nothing is written to disk
+    /// until the store is saved. On error, the entries merged so far are kept.
+    /// See [`merge_with`](Self::merge_with) to combine the values of such keys instead.
+    pub fn merge(&mut self, other: Self, on_duplicate: OnDuplicate) -> Result<usize> {
+        self.insert_all(other.into_map(), on_duplicate)
+    }
+
+    /// Inserts an entry unless `on_duplicate` says otherwise, returning whether it was inserted.
+    fn insert_with(&mut self, key: K, value: V, on_duplicate: OnDuplicate) -> Result<bool> {
+        if self.map.contains_key(&key) {
+            match on_duplicate {
+                OnDuplicate::Overwrite => {}
+                OnDuplicate::Skip => return Ok(false),
+                OnDuplicate::Error => {
+                    let raw = rmp_serde::encode::to_vec(&key)?;
+                    return Err(Error::DuplicateKey(describe_key(&raw)));
+                }
+            }
+        }
+
+        self.insert(key, value);
+        Ok(true)
+    }
+}
+
+impl<V, S> Store<String, V, S>
+where
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Counts the keys of the store at `options.path` that start with `prefix`, e.g. every
+    /// `user:` key in a namespaced scheme. Like [`load_keys`](Self::load_keys), no values are decoded.
+    pub fn count_key_prefix(options: &StoreOptions, prefix: &str) -> Result<usize> {
+        let keys = Self::load_keys(options)?;
+        Ok(keys.iter().filter(|key| key.starts_with(prefix)).count())
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Whether the store file at `path` is in an older format version than this library writes,
+    /// reading only its header. Such a file can still be opened, and saving it rewrites it in the
+    /// current version. A missing file needs no migration, and versions that can't be read fail
+    /// with `Error::FileTooNew` or `Error::FileTooOld`.
+    pub fn needs_migration(path: impl AsRef<Path>) -> Result<bool> {
+        let path = path.as_ref();
+        if !is_store_file(path)? {
+            return Ok(false);
+        }
+
+        let version = read_version(File::open(path)?, &StoreOptions::default())?;
+        Ok(version < VERSION)
+    }
+
+    /// Atomically moves the store file at `from` to `to`, replacing any file there, e.g. to put a
+    /// store built at a staging path into service. `from` must have a valid header, so a file that
+    /// isn't a store, or is from an unsupported version, is never moved into place. Its delta
+    /// file, if any, moves along with it. Both paths must be on the same filesystem.
+    pub fn promote(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        payload_reader(BufReader::new(File::open(from)?), &StoreOptions::default())?;
+
+        match replace_file(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                return Err(Error::CrossFilesystem);
+            }
+            result => result?,
+        }
+
+        match std::fs::rename(delta::delta_path(from), delta::delta_path(to)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => delta::remove(to)?,
+            result => result?,
+        }
+
+        Ok(())
+    }
+
+    /// Copies a store from `src` to `dst`, checking that it is intact along the way, and returns
+    /// the number of bytes copied. The header must be supported and the payload must decompress
+    /// and decode completely, but entries are only skipped over, never materialized, so memory use
+    /// stays bounded regardless of the store's size. Everything after the store in `src` is copied
+    /// too. If the store is damaged, `dst` will have received part of it before the error.
+    pub fn copy_verified<R: Read, W: Write>(src: R, dst: W) -> Result<u64> {
+        let mut reader = BufReader::new(CopyReader {
+            inner: src,
+            writer: dst,
+            copied: 0,
+        });
+
+        let mut payload = payload_reader(&mut reader, &StoreOptions::default())?;
+        let len =
+            rmp::decode::read_array_len(&mut payload).map_err(rmp_serde::decode::Error::from)?;
+        if len != section_count(payload.flags) {
+            return Err(Error::InvalidFile);
+        }
+        for _ in 0..len {
+            IgnoredAny::deserialize(&mut rmp_serde::Deserializer::new(&mut payload))?;
+        }
+        payload.finish()?;
+
+        io::copy(&mut reader, &mut io::sink())?;
+        let copier = reader.into_inner();
+        Ok(copier.copied)
+    }
+
+    /// Removes the temporary file that a crashed [`save`](Self::save) can leave next to
+    /// `options.path`, returning whether there was one to remove. A temporary file modified less
+    /// than `older_than` ago is left alone, since it may belong to a save that is still running.
+    pub fn cleanup_temp(options: &StoreOptions, older_than: Duration) -> Result<bool> {
+        let path = temp_path(&options.path);
+        let modified = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        // A modification time in the future counts as brand new.
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age < older_than {
+            return Ok(false);
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Reads the summary of a store file from its header and the start of its payload.
+pub(crate) fn inspect(path: &Path) -> Result<StoreInfo> {
+    let file = File::open(path)?;
+    let stamp = FileStamp::of(&file)?;
+    let mut reader = BufReader::new(file);
+
+    let mut magic_id = [0u8; 4];
+    reader.read_exact(&mut magic_id)?;
+    if magic_id != MAGIC_ID {
+        return Err(Error::InvalidFile);
+    }
+
+    let mut info = StoreInfo {
+        version: reader.read_u16::<LittleEndian>()?,
+        compressed: None,
+        compression_level: None,
+        entry_count: None,
+        size: stamp.len,
+        modified: stamp.modified,
+    };
+
+    // Older versions only record the entry count inside the payload, which isn't worth
+    // decompressing just to summarize the file.
+    match info.version {
+        3 => info.compressed = Some(true),
+        4..=5 => info.compressed = Some(reader.read_u16::<LittleEndian>()? & FLAG_COMPRESSED != 0),
+        6..=VERSION => {
+            let flags = reader.read_u16::<LittleEndian>()?;
+            let entry_count = reader.read_u64::<LittleEndian>()?;
+            let level = reader.read_i32::<LittleEndian>()?;
+            let compressed = flags & FLAG_COMPRESSED != 0;
+            info.compressed = Some(compressed);
+            info.compression_level = compressed.then(|| CompressionLevel::new(level));
+            if !delta::exists(path, stamp)? {
+                info.entry_count = Some(entry_count as usize);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(info)
+}
+
+/// Atomically replaces `to` with `from`.
+/// On Windows, `rename` already replaces an existing file, but it fails with access denied while
+/// another process, such as a virus scanner or indexer, briefly has the target open without
+/// sharing deletion. Those handles are usually released within moments, so retry a few times
+/// before giving up.
+pub(crate) fn replace_file(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    for delay in [10, 50, 250] {
+        match std::fs::rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                std::thread::sleep(Duration::from_millis(delay));
+            }
+            result => return result,
+        }
+    }
+
+    std::fs::rename(from, to)?;
+    sync_parent(to)
+}
+
+/// Flushes the directory containing `path` to disk.
+/// On Unix, a rename is only durable once its directory is, otherwise a crash can leave the
+/// directory without the renamed file. Windows has no equivalent, so this does nothing there.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Fails with `Error::InvalidPath` if `path` is empty, as it is in options without a path, rather
+/// than letting the filesystem resolve it somewhere surprising.
+pub(crate) fn check_path(path: &Path) -> Result<()> {
+    match path.as_os_str().is_empty() {
+        true => Err(Error::InvalidPath),
+        false => Ok(()),
+    }
+}
+
+/// Whether there is a store file at `path`, failing with `Error::NotAFile` if something other
+/// than a file is there.
+fn is_store_file(path: &Path) -> Result<bool> {
+    check_path(path)?;
+    if !path.exists() {
+        Ok(false)
+    } else if !path.is_file() {
+        Err(Error::NotAFile(path.to_path_buf()))
+    } else {
+        Ok(true)
+    }
+}
+
+/// A store written to its temporary file by `Store::stage`, waiting to be moved into place.
+#[derive(Debug)]
+pub struct StagedSave {
+    pub(crate) temp_path: PathBuf,
+    stamp: FileStamp,
+}
+
+/// A reader that writes everything read through it to `writer`.
+struct CopyReader<R, W> {
+    inner: R,
+    writer: W,
+    copied: u64,
+}
+
+impl<R: Read, W: Write> Read for CopyReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        self.copied += n as u64;
+        Ok(n)
+    }
+}
+
+/// The file `save` writes to before renaming it over the store at `path`.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    path.with_extension("ogma.tmp")
+}
+
+/// Identifies a particular version of a file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    pub(crate) len: u64,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub(crate) fn of(file: &File) -> io::Result<Self> {
+        let metadata = file.metadata()?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    /// The stamp of the file at `path`, or `None` if there is no such file.
+    pub(crate) fn of_path(path: &Path) -> io::Result<Option<Self>> {
+        match File::open(path) {
+            Ok(file) => Self::of(&file).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The stamp of the file a store was last opened from or saved to, or `None` if there was no file.
+/// It is updated by `save`, which only borrows the store, hence the mutex.
+#[derive(Debug, Default)]
+pub(crate) struct DiskStamp(Mutex<Option<FileStamp>>);
+
+impl DiskStamp {
+    pub(crate) fn get(&self) -> Option<FileStamp> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set(&self, stamp: Option<FileStamp>) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = stamp;
+    }
+}
+
+impl Clone for DiskStamp {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.get()))
+    }
+}
+
+/// Counts the changes made to a store, and which of them were last saved.
+/// The saved revision is updated by `save`, which only borrows the store, hence the atomic.
+#[derive(Debug, Default)]
+pub(crate) struct Revision {
+    current: u64,
+    saved: AtomicU64,
+}
+
+impl Revision {
+    pub(crate) fn bump(&mut self) {
+        self.current += 1;
+    }
+
+    pub(crate) fn is_saved(&self) -> bool {
+        self.saved() == self.current
+    }
+
+    pub(crate) fn saved(&self) -> u64 {
+        self.saved.load(atomic::Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_saved(&self, revision: u64) {
+        self.saved.store(revision, atomic::Ordering::Relaxed);
+    }
+
+    pub(crate) fn mark_saved(&self) {
+        self.set_saved(self.current);
+    }
+}
+
+impl Clone for Revision {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current,
+            saved: AtomicU64::new(self.saved()),
+        }
+    }
+}
+
+/// How to flush a store with `StoreOptions::autosave` when it's dropped. `Drop` can't require the
+/// store to be serializable, so this is only known once a method that can save it was called.
+/// It is set by `save`, and handed over by `CowSharedStore::update`, which only borrow the store,
+/// hence the mutex. Clones start without one, so that only one copy of a store flushes.
+pub(crate) struct DropFlush<K, V, S>(Mutex<Option<FlushFn<K, V, S>>>)
+where
+    K: Eq + Hash,
+    S: BuildHasher;
+
+type FlushFn<K, V, S> = fn(&Store<K, V, S>) -> Result<()>;
+
+impl<K, V, S> DropFlush<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub(crate) fn set(&self, flush: Option<FlushFn<K, V, S>>) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = flush;
+    }
+
+    pub(crate) fn take(&self) -> Option<FlushFn<K, V, S>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+}
+
+impl<K, V, S> Default for DropFlush<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn default() -> Self {
+        Self(Mutex::new(None))
+    }
+}
+
+impl<K, V, S> Clone for DropFlush<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V, S> fmt::Debug for DropFlush<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some();
+        f.debug_tuple("DropFlush").field(&set).finish()
+    }
+}
+
+impl<K, V, S> Drop for Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Flushes a store with `StoreOptions::autosave` that has unsaved changes, ignoring any error.
+    fn drop(&mut self) {
+        if let Some(flush) = self.drop_flush.take()
+            && self.options.autosave
+            && !self.revision.is_saved()
+        {
+            let _ = flush(self);
+        }
+    }
+}
+
+/// Atomically writes a store file at `options.path` whose payload holds only a map of `len`
+/// entries, which `write_entries` encodes as alternating keys and values, for entries that aren't
+/// held by a [`Store`]. Backups are rotated and any delta file is removed, as for a save.
+pub(crate) fn write_entries_file<F>(
+    options: &StoreOptions,
+    len: u64,
+    write_entries: F,
+) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    check_path(&options.path)?;
+    let len = u32::try_from(len).map_err(|_| io::Error::other("too many entries for a store"))?;
+    if options.create_dirs
+        && let Some(parent) = options.path.parent()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = temp_path(&options.path);
+    let write = || -> Result<()> {
+        let level = options.compression_level;
+        let mut flags = if level.is_none() { 0 } else { FLAG_COMPRESSED };
+        if options.type_tag.is_some() {
+            flags |= FLAG_TYPE_TAG;
+        }
+
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        file.write_all(MAGIC_ID)?;
+        file.write_u16::<LittleEndian>(VERSION)?;
+        let mut file = Blake3Writer::new(file);
+        file.write_u16::<LittleEndian>(flags)?;
+        file.write_u64::<LittleEndian>(len as u64)?;
+        file.write_i32::<LittleEndian>(level.level())?;
+        if let Some(tag) = options.type_tag {
+            file.write_u32::<LittleEndian>(tag)?;
+        }
+
+        let write_payload = |mut payload: &mut dyn Write| -> Result<()> {
+            rmp::encode::write_array_len(&mut payload, 1)
+                .map_err(rmp_serde::encode::Error::from)?;
+            rmp::encode::write_map_len(&mut payload, len)
+                .map_err(rmp_serde::encode::Error::from)?;
+            write_entries(payload)
+        };
+
+        let file = if level.is_none() {
+            write_payload(&mut file)?;
+            file
+        } else {
+            let mut enc = Encoder::new(file, level.level())?;
+            if options.compression_threads > 1 {
+                enc.multithread(options.compression_threads)?;
+            }
+            write_payload(&mut enc)?;
+            enc.finish()?
+        };
+
+        let file = write_trailer(file)?;
+        file.into_inner().map_err(io::Error::from)?.sync_all()?;
+        Ok(())
+    };
+
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    backup::rotate(&options.path, options.backups)?;
+    replace_file(&temp_path, &options.path)?;
+    delta::remove(&options.path)?;
+    Ok(())
+}
+
+/// Ends a store with the checksum of everything after its version.
+fn write_trailer<W: Write>(writer: Blake3Writer<W>) -> io::Result<W> {
+    let (mut writer, checksum) = writer.finalize();
+    writer.write_all(checksum.as_bytes())?;
+    Ok(writer)
+}
+
+/// The number of elements in the payload array for the given flags.
+fn section_count(flags: u16) -> u32 {
+    1 + (flags & FLAG_TOMBSTONES != 0) as u32
+        + (flags & FLAG_CHECKSUMS != 0) as u32
+        + (flags & FLAG_EXPIRIES != 0) as u32
+}
+
+/// The body of a store file along with the header flags and metadata describing it.
+struct Payload<R: BufRead> {
+    flags: u16,
+    metadata: Option<Vec<u8>>,
+    sequence: u64,
+    body: Body<Blake3Reader<R>>,
+
+    /// The format version the store was written in.
+    version: u16,
+
+    /// How many more bytes may be read from the body, if it is limited.
+    remaining: Option<u64>,
+
+    /// Set once a read went past the limit, since the error itself may reach the caller wrapped
+    /// in a decode error.
+    exceeded: bool,
+}
+
+/// The body of a store file, decompressing it if necessary.
+enum Body<R: BufRead> {
+    Raw(R),
+    Compressed(Decoder<'static, R>),
+}
+
+impl<R: BufRead> Payload<R> {
+    /// Consumes the rest of the body and the checksum trailer so that the underlying reader is
+    /// left at the end of the store, failing with `Error::ChecksumMismatch` if the file is
+    /// corrupted. A raw body is self-delimiting and already fully read once the payload is
+    /// decoded, but a compressed one still has the end of its frame left. Versions before 5 have
+    /// no trailer to check.
+    fn finish(&mut self) -> Result<()> {
+        if let Body::Compressed(_) = self.body {
+            io::copy(self, &mut io::sink())?;
+        }
+        if self.version < 5 {
+            return Ok(());
+        }
+
+        let reader = match &mut self.body {
+            Body::Raw(reader) => reader,
+            Body::Compressed(decoder) => decoder.get_mut(),
+        };
+        let actual = reader.digest();
+        let mut expected = [0u8; blake3::OUT_LEN];
+        reader.get_mut().read_exact(&mut expected)?;
+        let expected = blake3::Hash::from_bytes(expected);
+        if expected != actual {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the error of a read that failed because it went past the limit.
+    fn check_limit<T>(&self, result: Result<T>) -> Result<T> {
+        match result {
+            Err(_) if self.exceeded => Err(Error::DecompressionLimitExceeded),
+            result => result,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Payload<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.body {
+            Body::Raw(reader) => reader.read(buf)?,
+            Body::Compressed(decoder) => decoder.read(buf)?,
+        };
+
+        if let Some(remaining) = &mut self.remaining {
+            match remaining.checked_sub(n as u64) {
+                Some(left) => *remaining = left,
+                None => {
+                    self.exceeded = true;
+                    return Err(io::Error::other("decompressed size limit exceeded"));
+                }
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+/// Validates the header of a store and returns a reader over the decompressed payload.
+/// Reading more than `options.max_decompressed_bytes` of payload fails with
+/// `Error::DecompressionLimitExceeded`, a type tag other than `options.type_tag` fails with
+/// `Error::TypeMismatch`, and a version mismatch is ignored if `options.force_open` is set.
+fn payload_reader<R: BufRead>(mut reader: R, options: &StoreOptions) -> Result<Payload<R>> {
+    let version = read_version(&mut reader, options)?;
+    versioned_payload_reader(reader, options, version)
+}
+
+/// Validates the magic ID and version of a store, returning the version to read it as.
+/// A version this library can't read is read as the current one if `options.force_open` is set.
+fn read_version<R: Read>(mut reader: R, options: &StoreOptions) -> Result<u16> {
+    let mut magic_id = [0u8; 4];
+    reader.read_exact(&mut magic_id)?;
+    if magic_id != MAGIC_ID {
+        return Err(Error::InvalidFile);
+    }
+
+    match reader.read_u16::<LittleEndian>()? {
+        version @ OLDEST_VERSION..=VERSION => Ok(version),
+        _ if options.force_open => Ok(VERSION),
+        version if version > VERSION => Err(Error::FileTooNew {
+            file: version,
+            supported: VERSION,
+        }),
+        version => Err(Error::FileTooOld {
+            file: version,
+            supported: OLDEST_VERSION,
+        }),
+    }
+}
+
+/// Like [`payload_reader`], but for a store written without its magic ID and version, starting at
+/// the flags.
+fn bare_payload_reader<R: BufRead>(reader: R, options: &StoreOptions) -> Result<Payload<R>> {
+    versioned_payload_reader(reader, options, VERSION)
+}
+
+/// Reads everything after the version of a store in format `version`.
+fn versioned_payload_reader<R: BufRead>(
+    reader: R,
+    options: &StoreOptions,
+    version: u16,
+) -> Result<Payload<R>> {
+    // Everything from the flags up to the trailer is covered by the checksum.
+    let mut reader = Blake3Reader::new(reader);
+    let flags = match version {
+        3 => FLAG_COMPRESSED,
+        _ => reader.read_u16::<LittleEndian>()?,
+    };
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(Error::UnsupportedFlags(flags & !KNOWN_FLAGS));
+    }
+    if version >= 6 {
+        // The entry count and compression level are only there for `inspect`.
+        reader.read_u64::<LittleEndian>()?;
+        reader.read_i32::<LittleEndian>()?;
+    }
+
+    let metadata = match flags & FLAG_METADATA != 0 {
+        true => {
+            let len = reader.read_u32::<LittleEndian>()?;
+            let mut metadata = Vec::new();
+            (&mut reader).take(len as u64).read_to_end(&mut metadata)?;
+            if metadata.len() != len as usize {
+                return Err(Error::InvalidFile);
+            }
+            Some(metadata)
+        }
+        false => None,
+    };
+
+    let sequence = match flags & FLAG_SEQUENCE != 0 {
+        true => reader.read_u64::<LittleEndian>()?,
+        false => 0,
+    };
+
+    if flags & FLAG_TYPE_TAG != 0 {
+        let found = reader.read_u32::<LittleEndian>()?;
+        match options.type_tag {
+            Some(expected) if expected != found => {
+                return Err(Error::TypeMismatch { expected, found });
+            }
+            _ => {}
+        }
+    }
+
+    let dictionary = match flags & FLAG_DICTIONARY != 0 {
+        true => {
+            let mut hash = [0u8; blake3::OUT_LEN];
+            reader.read_exact(&mut hash)?;
+            let dictionary = options
+                .dictionary
+                .as_deref()
+                .ok_or(Error::DictionaryRequired)?;
+            if blake3::hash(dictionary) != blake3::Hash::from_bytes(hash) {
+                return Err(Error::DictionaryMismatch);
+            }
+            Some(dictionary)
+        }
+        false => None,
+    };
+
+    let body = if flags & FLAG_COMPRESSED != 0 {
+        let decoder = match dictionary {
+            Some(dictionary) => Decoder::with_dictionary(reader, dictionary)?,
+            None => Decoder::with_buffer(reader)?,
+        };
+        // Stop at the end of the frame so that nothing after the store is consumed.
+        Body::Compressed(decoder.single_frame())
+    } else {
+        Body::Raw(reader)
+    };
+
+    Ok(Payload {
+        flags,
+        metadata,
+        sequence,
+        body,
+        version,
+        remaining: options.max_decompressed_bytes,
+        exceeded: false,
+    })
+}
+
+/// Validates the header of a store and the array of sections in its payload, returning a reader
+/// positioned at the map of entries.
+fn sections_reader<R: BufRead>(
+    reader: R,
+    options: &StoreOptions,
+) -> Result<KeyRecorder<Payload<R>>> {
+    sections_of(payload_reader(reader, options)?)
+}
+
+/// Validates the array of sections in a payload, returning a reader positioned at the map of
+/// entries.
+fn sections_of<R: BufRead>(payload: Payload<R>) -> Result<KeyRecorder<Payload<R>>> {
+    let mut reader = KeyRecorder {
+        inner: payload,
+        key: Vec::new(),
+    };
+
+    let len = rmp::decode::read_array_len(&mut reader).map_err(rmp_serde::decode::Error::from)?;
+    if len != section_count(reader.inner.flags) {
+        return Err(Error::InvalidFile);
+    }
+
+    Ok(reader)
+}
+
+/// The most entries to reserve room for up front. The length recorded in a file can't be trusted,
+/// so larger maps grow as their entries are actually decoded.
+const MAX_PREALLOCATED_ENTRIES: usize = 4096;
+
+/// Decodes the map of entries one at a time so that a value that fails to decode can be reported
+/// along with its key.
+fn read_entries_into<R, K, V, S>(
+    reader: &mut KeyRecorder<R>,
+    map: &mut HashMap<K, V, S>,
+) -> Result<()>
+where
+    R: Read,
+    K: Eq + Hash + for<'de> Deserialize<'de>,
+    V: for<'de> Deserialize<'de>,
+    S: BuildHasher,
+{
+    let entries = Entries::new(reader)?;
+    map.reserve(entries.remaining.min(MAX_PREALLOCATED_ENTRIES));
+    for entry in entries {
+        let (key, value) = entry?;
+        map.insert(key, value);
+    }
+
+    Ok(())
+}
+
+/// Streams the entries of a serialized map without collecting them.
+struct Entries<'r, R, K, V> {
+    reader: &'r mut KeyRecorder<R>,
+    remaining: usize,
+    _entry: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'r, R: Read, K, V> Entries<'r, R, K, V> {
+    /// Reads the map's header, leaving the reader at its first entry.
+    fn new(reader: &'r mut KeyRecorder<R>) -> Result<Self> {
+        let len = rmp::decode::read_map_len(reader).map_err(rmp_serde::decode::Error::from)?;
+        Ok(Self {
+            reader,
+            remaining: len as usize,
+            _entry: PhantomData,
+        })
+    }
+}
+
+impl<R, K, V> Iterator for Entries<'_, R, K, V>
+where
+    R: Read,
+    K: for<'de> Deserialize<'de>,
+    V: for<'de> Deserialize<'de>,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let reader = &mut *self.reader;
+        reader.key.clear();
+        let key = match K::deserialize(&mut rmp_serde::Deserializer::new(&mut *reader)) {
+            Ok(key) => key,
+            Err(e) => {
+                // The rest of the map can't be followed past a key that failed to decode.
+                self.remaining = 0;
+                return Some(Err(e.into()));
+            }
+        };
+
+        let raw_key = std::mem::take(&mut reader.key);
+        let value = V::deserialize(&mut rmp_serde::Deserializer::new(&mut *reader));
+        let result = match value {
+            Ok(value) => Ok((key, value)),
+            Err(source) => {
+                self.remaining = 0;
+                Err(Error::ValueDecode {
+                    key: describe_key(&raw_key),
+                    source,
+                })
+            }
+        };
+
+        reader.key = raw_key;
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+/// Records the bytes read while decoding a key so it can be described in an error without
+/// requiring `K: Debug`.
+struct KeyRecorder<R> {
+    inner: R,
+    key: Vec<u8>,
+}
+
+impl<R: Read> Read for KeyRecorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.key.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Renders an encoded key for diagnostics.
+/// Strings and integers are shown as-is, and byte strings as hex.
+/// Anything else is shown as the hex of its encoding.
+pub(crate) fn describe_key(raw: &[u8]) -> String {
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect();
+    if let Ok(key) = rmp_serde::decode::from_slice::<String>(raw) {
+        format!("{key:?}")
+    } else if let Ok(key) = rmp_serde::decode::from_slice::<i64>(raw) {
+        key.to_string()
+    } else if let Ok(key) = rmp_serde::decode::from_slice::<u64>(raw) {
+        key.to_string()
+    } else if let Ok(key) = rmp_serde::decode::from_slice::<Vec<u8>>(raw) {
+        hex(&key)
+    } else {
+        hex(raw)
+    }
+}
+
+/// Decodes a single value from the cursor, or skips over it if it is well-formed MessagePack but
+/// not a valid `T`. Returns `None` when the value can't even be skipped.
+fn decode_or_skip<T>(cursor: &mut Cursor<&[u8]>) -> Option<Option<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let start = cursor.position();
+    if let Ok(value) = T::deserialize(&mut rmp_serde::Deserializer::new(&mut *cursor)) {
+        return Some(Some(value));
+    }
+
+    cursor.set_position(start);
+    IgnoredAny::deserialize(&mut rmp_serde::Deserializer::new(&mut *cursor)).ok()?;
+    Some(None)
+}
+
+/// Mirrors the layout of the payload but keeps only the keys of its map.
+#[derive(Deserialize)]
+#[serde(bound = "K: Deserialize<'de> + Eq + Hash")]
+struct KeysOnly<K> {
+    map: KeySet<K>,
+
+    #[serde(default)]
+    _tombstones: IgnoredAny,
+
+    #[serde(default)]
+    _checksums: IgnoredAny,
+
+    #[serde(default)]
+    _expiries: IgnoredAny,
+}
+
+struct KeySet<K>(HashSet<K>);
+
+impl<'de, K> Deserialize<'de> for KeySet<K>
+where
+    K: Deserialize<'de> + Eq + Hash,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct KeySetVisitor<K>(PhantomData<K>);
+
+        impl<'de, K> Visitor<'de> for KeySetVisitor<K>
+        where
+            K: Deserialize<'de> + Eq + Hash,
+        {
+            type Value = KeySet<K>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut keys = HashSet::with_capacity(
+                    map.size_hint().unwrap_or(0).min(MAX_PREALLOCATED_ENTRIES),
+                );
+                while let Some(key) = map.next_key()? {
+                    map.next_value::<IgnoredAny>()?;
+                    keys.insert(key);
+                }
+
+                Ok(KeySet(keys))
+            }
+        }
+
+        deserializer.deserialize_map(KeySetVisitor(PhantomData))
+    }
+}
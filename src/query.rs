@@ -0,0 +1,93 @@
+use crate::error::Result;
+use crate::store::Store;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+use serde::Serialize;
+use serde_json::Value;
+
+/// A comparison applied by [`Store::query`] between a field and an operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    /// The field is a string containing the operand as a substring,
+    /// or an array containing an element equal to the operand.
+    Contains,
+}
+
+impl Op {
+    fn matches(self, field: &Value, operand: &Value) -> bool {
+        match self {
+            Op::Eq => field == operand,
+            Op::Ne => field != operand,
+            Op::Lt => compare(field, operand) == Some(Ordering::Less),
+            Op::Le => matches!(
+                compare(field, operand),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Op::Gt => compare(field, operand) == Some(Ordering::Greater),
+            Op::Ge => matches!(
+                compare(field, operand),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Op::Contains => match (field, operand) {
+                (Value::String(field), Value::String(operand)) => field.contains(operand.as_str()),
+                (Value::Array(items), operand) => items.contains(operand),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Orders two values of the same kind. Numbers and strings are ordered, anything else is not.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Follows a dot-separated path of object keys and array indices, e.g. `address.state` or `tags.0`.
+/// An empty path refers to the value itself.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    V: Serialize,
+    S: BuildHasher + Default,
+{
+    /// Returns every entry whose value has a field at `path` that satisfies `op` against `operand`.
+    /// Each value is converted to JSON to evaluate the query, so this is much slower than filtering
+    /// with a closure, but it lets generic tooling filter a store without knowing its value type.
+    /// Entries that don't have a field at `path` never match.
+    pub fn query(&self, path: &str, op: Op, operand: Value) -> Result<Vec<(&K, &V)>> {
+        let mut matches = Vec::new();
+        for (key, value) in self.map.iter() {
+            let json = serde_json::to_value(value)?;
+            if lookup(&json, path).is_some_and(|field| op.matches(field, &operand)) {
+                matches.push((key, value));
+            }
+        }
+
+        Ok(matches)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::error::{Error, Result};
+use crate::persist::write_entries_file;
+use crate::store::{CompressionLevel, Store, StoreOptions};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::Path;
+use zstd::{Decoder, Encoder};
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Ord + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Writes `entries`, which must be sorted by strictly increasing key, to a run file at `path`
+    /// for [`merge_runs`](Self::merge_runs), streaming them so they never have to fit in memory.
+    /// Returns the number of entries written. An entry out of order fails with
+    /// `Error::UnsortedRun` and leaves a partial run behind. Run files are compressed at
+    /// `CompressionLevel::DEFAULT` and aren't stores themselves.
+    pub fn write_sorted_run<I>(path: impl AsRef<Path>, entries: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let file = BufWriter::new(File::create(path)?);
+        let mut enc = Encoder::new(file, CompressionLevel::DEFAULT.level())?;
+        let mut previous: Option<K> = None;
+        let mut written = 0;
+        for (key, value) in entries {
+            if previous.as_ref().is_some_and(|previous| *previous >= key) {
+                return Err(Error::UnsortedRun);
+            }
+
+            rmp_serde::encode::write(&mut enc, &key)?;
+            rmp_serde::encode::write(&mut enc, &value)?;
+            previous = Some(key);
+            written += 1;
+        }
+
+        enc.finish()?
+            .into_inner()
+            .map_err(io::Error::from)?
+            .sync_all()?;
+        Ok(written)
+    }
+
+    /// Merges the run files written by [`write_sorted_run`](Self::write_sorted_run) into a store
+    /// file at `options.path`, replacing any file there atomically, and returns the number of
+    /// entries in it. Only one entry of each run is held in memory at a time. Where several runs
+    /// hold the same key, the entry from the run listed last wins. The runs are read twice, first
+    /// to count the entries and then to write them, and are left in place.
+    pub fn merge_runs<P: AsRef<Path>>(runs: &[P], options: &StoreOptions) -> Result<u64> {
+        let mut count = 0u64;
+        merge(runs, |_: K, _: V| {
+            count += 1;
+            Ok(())
+        })?;
+        write_entries_file(options, count, |enc| {
+            merge(runs, |key: K, value: V| {
+                rmp_serde::encode::write(enc, &key)?;
+                rmp_serde::encode::write(enc, &value)?;
+                Ok(())
+            })
+        })?;
+        Ok(count)
+    }
+}
+
+/// The next entry of one run, ordered so that a `BinaryHeap` yields the smallest key first, and
+/// among equal keys the entry of the run listed last.
+struct Head<K, V> {
+    key: K,
+    value: V,
+    run: usize,
+}
+
+impl<K: Ord, V> Ord for Head<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.cmp(&self.key).then(self.run.cmp(&other.run))
+    }
+}
+
+impl<K: Ord, V> PartialOrd for Head<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> PartialEq for Head<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, V> Eq for Head<K, V> {}
+
+type RunReader = BufReader<Decoder<'static, BufReader<File>>>;
+
+/// Decodes the next entry of a run, or `None` at its end.
+fn next_entry<K, V>(reader: &mut RunReader) -> Result<Option<(K, V)>>
+where
+    K: for<'de> Deserialize<'de>,
+    V: for<'de> Deserialize<'de>,
+{
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    let key = rmp_serde::decode::from_read(&mut *reader)?;
+    let value = rmp_serde::decode::from_read(&mut *reader)?;
+    Ok(Some((key, value)))
+}
+
+/// Passes the entries of every run to `f` in order of their keys, once per key.
+fn merge<P, K, V, F>(runs: &[P], mut f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    K: Ord + for<'de> Deserialize<'de>,
+    V: for<'de> Deserialize<'de>,
+    F: FnMut(K, V) -> Result<()>,
+{
+    let mut readers = Vec::with_capacity(runs.len());
+    let mut heads = BinaryHeap::with_capacity(runs.len());
+    for (run, path) in runs.iter().enumerate() {
+        let mut reader = BufReader::new(Decoder::new(File::open(path)?)?);
+        if let Some((key, value)) = next_entry(&mut reader)? {
+            heads.push(Head { key, value, run });
+        }
+        readers.push(reader);
+    }
+
+    while let Some(head) = heads.pop() {
+        // Entries with the same key from earlier runs are superseded by this one.
+        while heads.peek().is_some_and(|next| next.key == head.key) {
+            let superseded = heads.pop().unwrap();
+            if let Some((key, value)) = next_entry(&mut readers[superseded.run])? {
+                heads.push(Head {
+                    key,
+                    value,
+                    run: superseded.run,
+                });
+            }
+        }
+
+        let run = head.run;
+        f(head.key, head.value)?;
+        if let Some((key, value)) = next_entry(&mut readers[run])? {
+            heads.push(Head { key, value, run });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use crate::error::Result;
+use crate::store::Store;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::ops::Add;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A cloneable, thread-safe handle to a [`Store`].
+/// Every clone refers to the same underlying store.
+///
+/// Large values that never change once inserted are best stored as `Arc<V>`, so that readers can
+/// take a reference with [`get_arc`](Self::get_arc) instead of deep-cloning the value or holding
+/// the read lock while using it. `Arc<V>` serializes exactly like `V`.
+#[derive(Debug)]
+pub struct SharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    inner: Arc<RwLock<Store<K, V>>>,
+}
+
+impl<K, V> Clone for SharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<K, V> From<Store<K, V>> for SharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from(store: Store<K, V>) -> Self {
+        Self::new(store)
+    }
+}
+
+impl<K, V> SharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn new(store: Store<K, V>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    /// Acquires a read lock on the store, blocking until no writer holds it.
+    /// A lock poisoned by a panicking thread is recovered rather than propagated.
+    pub fn read(&self) -> RwLockReadGuard<'_, Store<K, V>> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquires a write lock on the store, blocking until all other locks are released.
+    /// A lock poisoned by a panicking thread is recovered rather than propagated.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Store<K, V>> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Clones the value of `key` under a brief read lock, so the lock is never held while the
+    /// value is used. See [`get_arc`](Self::get_arc) for values that are expensive to clone.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.read().get(key).cloned()
+    }
+
+    /// Like [`Store::insert`], under a brief write lock.
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        K: Clone,
+    {
+        self.write().insert(key, value)
+    }
+
+    /// Like [`Store::remove`], under a brief write lock.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write().remove(key)
+    }
+
+    /// Like [`Store::increment`], but under a single write lock so that concurrent increments of
+    /// the same key are never lost.
+    pub fn increment(&self, key: &K, delta: V) -> V
+    where
+        K: Clone,
+        V: Add<Output = V> + Copy + Default,
+    {
+        self.write().increment(key, delta)
+    }
+}
+
+impl<K, V> SharedStore<K, V>
+where
+    K: Eq + Hash + Ord + Clone,
+{
+    /// Creates a cursor over a sorted snapshot of the keys currently in the store, for paging
+    /// through it while other threads write to it. See [`Cursor`].
+    pub fn cursor(&self) -> Cursor<K, V> {
+        let mut keys: Vec<K> = self.read().keys().cloned().collect();
+        keys.sort_unstable();
+        Cursor {
+            store: self.clone(),
+            keys: keys.into_iter(),
+        }
+    }
+}
+
+/// Forward-only pagination over a [`SharedStore`], created by [`SharedStore::cursor`].
+///
+/// The cursor visits the keys that were present when it was created, in order. Each page takes
+/// the read lock only while it's being collected and returns the entries' current values, so
+/// keys removed since the cursor was created are skipped and keys inserted since are never seen.
+#[derive(Debug)]
+pub struct Cursor<K, V>
+where
+    K: Eq + Hash,
+{
+    store: SharedStore<K, V>,
+    keys: std::vec::IntoIter<K>,
+}
+
+impl<K, V> Cursor<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    /// Returns up to `n` of the next entries that are still present, or fewer once the cursor
+    /// reaches the end of its snapshot.
+    pub fn next_page(&mut self, n: usize) -> Vec<(K, V)> {
+        let store = self.store.read();
+        let mut page = Vec::with_capacity(n.min(self.keys.len()));
+        while page.len() < n {
+            let Some(key) = self.keys.next() else {
+                break;
+            };
+            if let Some(value) = store.get(&key) {
+                let value = value.clone();
+                page.push((key, value));
+            }
+        }
+
+        page
+    }
+
+    /// How many keys of the snapshot haven't been visited yet, including any that were removed.
+    pub fn remaining(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+impl<K, V> SharedStore<K, Arc<V>>
+where
+    K: Eq + Hash,
+{
+    /// Clones the `Arc` holding the value of `key` under a brief read lock.
+    pub fn get_arc<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read().get(key).cloned()
+    }
+}
+
+impl<K, V> SharedStore<K, V>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+{
+    /// Saves the store while holding a read lock for the entire serialization and compression.
+    /// Readers are never blocked, but writers wait until the save completes.
+    /// Prefer [`save`](Self::save) for large stores that are written to frequently.
+    pub fn save_locked(&self) -> Result<()> {
+        self.read().save()
+    }
+}
+
+impl<K, V> SharedStore<K, V>
+where
+    K: Eq + Hash + Clone + Serialize + for<'de> Deserialize<'de>,
+    V: Clone + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Saves a snapshot of the store.
+    /// The store is cloned under a brief read lock, and the slow serialization and compression
+    /// happen on the clone after the lock is released, so writers are only blocked for the copy.
+    /// The trade-off is that the whole store is held in memory twice while saving.
+    /// Use [`save_locked`](Self::save_locked) when that memory overhead is not acceptable.
+    pub fn save(&self) -> Result<()> {
+        let snapshot = self.read().clone();
+        snapshot.save()?;
+
+        // Saving updated the snapshot's record of the file, so carry it back to the live store.
+        // Changes made since the snapshot was taken have a later revision and remain unsaved.
+        let live = self.read();
+        live.disk_stamp.set(snapshot.disk_stamp.get());
+        live.revision.set_saved(snapshot.revision.saved());
+        Ok(())
+    }
+}
+
+/// A cloneable, thread-safe handle to a [`Store`] that is replaced wholesale on every write.
+/// Every clone refers to the same underlying store.
+///
+/// Readers take a snapshot with [`load`](Self::load), which only holds a lock long enough to
+/// clone an `Arc`, so they never wait on a writer and see a consistent view for as long as they
+/// keep the snapshot. Each [`update`](Self::update) instead clones the entire store, modifies the
+/// clone, and swaps it in, with updates serialized against each other.
+/// This suits stores that are read constantly and rarely written, such as configuration; prefer
+/// [`SharedStore`] for stores that are written to frequently.
+#[derive(Debug)]
+pub struct CowSharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    current: Arc<RwLock<Arc<Store<K, V>>>>,
+    writer: Arc<Mutex<()>>,
+}
+
+impl<K, V> Clone for CowSharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+            writer: Arc::clone(&self.writer),
+        }
+    }
+}
+
+impl<K, V> From<Store<K, V>> for CowSharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from(store: Store<K, V>) -> Self {
+        Self::new(store)
+    }
+}
+
+impl<K, V> CowSharedStore<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn new(store: Store<K, V>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(store))),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Returns a snapshot of the current store.
+    /// The snapshot is unaffected by later updates.
+    pub fn load(&self) -> Arc<Store<K, V>> {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl<K, V> CowSharedStore<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Clones the current store, applies `f` to the clone, and publishes it as the new current
+    /// store. Concurrent updates run one at a time, so none are lost.
+    /// If `f` panics, the current store is left unchanged.
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Store<K, V>) -> R,
+    {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let current = self.load();
+        let mut next = Store::clone(&current);
+        let result = f(&mut next);
+
+        // Only the newest store flushes when it's dropped, so older snapshots never overwrite it.
+        next.drop_flush.set(current.drop_flush.take());
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(next);
+        result
+    }
+}
+
+impl<K, V> CowSharedStore<K, V>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
+    V: Serialize + for<'de> Deserialize<'de>,
+{
+    /// Saves a snapshot of the store without blocking readers or writers.
+    pub fn save(&self) -> Result<()> {
+        let snapshot = self.load();
+        snapshot.save()?;
+
+        // An update may have replaced the snapshot while it was saving, so carry the record of
+        // the file forward to the live store.
+        let live = self.load();
+        live.disk_stamp.set(snapshot.disk_stamp.get());
+        live.revision.set_saved(snapshot.revision.saved());
+        Ok(())
+    }
+}
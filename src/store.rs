@@ -1,18 +1,34 @@
-use crate::error::{Error, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "std")]
+use crate::access::EntryMeta;
+#[cfg(feature = "std")]
+use crate::checksum::Digest;
+#[cfg(feature = "std")]
+use crate::delta::{ChangeSet, DeltaEnd};
+#[cfg(feature = "std")]
+use crate::event::{ChangeEvent, ChangeHook, Hook, StoreEvent};
+#[cfg(feature = "std")]
+use crate::index::Indexes;
+#[cfg(feature = "std")]
+use crate::lock::FileLock;
+#[cfg(feature = "std")]
+use crate::metrics::{Metrics, Operation};
+#[cfg(feature = "std")]
+use crate::order::InsertionOrder;
+#[cfg(feature = "std")]
+use crate::persist::{DiskStamp, DropFlush, Revision};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::ops::{Add, Deref, Range};
+use core::time::Duration;
+use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::hash::Hash;
-use std::io::{Read, Write};
-use std::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
-use zstd::{Decoder, Encoder};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
 
-const MAGIC_ID: &[u8] = b"OGMA";
-const VERSION: u16 = 3;
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CompressionLevel(i32);
 
 /// The compression level to use when writing to disk.
@@ -42,6 +58,12 @@ impl CompressionLevel {
     /// Slower compression speeds but higher compression ratios. Maps to 9 in zstd.
     /// Recommended for cases where a smaller size is more important than speed.
     pub const OPTIMAL: CompressionLevel = CompressionLevel(9);
+
+    /// No compression at all, e.g. for values that are already compressed or encrypted, where
+    /// zstd would only burn CPU. The header records that the payload is uncompressed, so the
+    /// file opens like any other. Delta layers are still zstd frames, at the fastest level.
+    pub const NONE: CompressionLevel = CompressionLevel(i32::MIN);
+
     pub const ZSTD_MIN: i32 = -7;
     pub const ZSTD_MAX: i32 = 22;
 
@@ -51,6 +73,46 @@ impl CompressionLevel {
     pub fn new(level: i32) -> Self {
         Self(level.clamp(Self::ZSTD_MIN, Self::ZSTD_MAX))
     }
+
+    /// The zstd level this maps to. For [`NONE`](Self::NONE), which isn't a zstd level, this is
+    /// `i32::MIN`.
+    pub const fn level(self) -> i32 {
+        self.0
+    }
+
+    /// Whether this is [`NONE`](Self::NONE).
+    pub const fn is_none(self) -> bool {
+        self.0 == Self::NONE.0
+    }
+
+    /// The pre-defined levels and their names, as used by [`name`](Self::name) and
+    /// [`from_name`](Self::from_name).
+    const NAMED: [(&'static str, CompressionLevel); 6] = [
+        ("none", Self::NONE),
+        ("fastest", Self::FASTEST),
+        ("fast", Self::FAST),
+        ("balanced", Self::BALANCED),
+        ("optimal", Self::OPTIMAL),
+        ("smallest", Self::SMALLEST_SIZE),
+    ];
+
+    /// The name of the pre-defined level this is equal to, such as `"balanced"`, or `None` for a
+    /// custom level. The default level is named `"fast"`.
+    pub fn name(self) -> Option<&'static str> {
+        Self::NAMED
+            .iter()
+            .find(|(_, level)| level.0 == self.0)
+            .map(|&(name, _)| name)
+    }
+
+    /// The pre-defined level with the given name, ignoring ASCII case.
+    /// Accepts the names returned by [`name`](Self::name).
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMED
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|&(_, level)| level)
+    }
 }
 
 impl Default for CompressionLevel {
@@ -59,20 +121,79 @@ impl Default for CompressionLevel {
     }
 }
 
+/// How a store coordinates with other processes using the same file, for
+/// `StoreOptions::access_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    /// Hold a shared lock from `open` until the store is dropped, so that no writer can save
+    /// while the store is open. Any number of readers can hold the lock at once.
+    Read,
+
+    /// Hold an exclusive lock while saving, which waits for every reader to close the store.
+    Write,
+}
+
+/// What a bulk insertion does with a key that is already present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnDuplicate {
+    /// Replace the existing value.
+    #[default]
+    Overwrite,
+
+    /// Keep the existing value and drop the new one.
+    Skip,
+
+    /// Stop at the first duplicate and fail with `Error::DuplicateKey`.
+    Error,
+}
+
 #[derive(Debug, Clone)]
 pub struct StoreOptions {
+    #[cfg(feature = "std")]
     pub path: PathBuf,
     pub compression_level: CompressionLevel,
+    pub compression_threads: u32,
+    pub check_before_overwrite: bool,
+    pub backups: usize,
+    pub min_compress_bytes: usize,
+    pub stable_order: bool,
+    pub tombstones: bool,
+    pub create_dirs: bool,
+    pub incremental: bool,
+    pub max_decompressed_bytes: Option<u64>,
+    pub auto_compact_ratio: Option<f32>,
+    pub entry_checksums: bool,
+    pub preallocate: bool,
+    pub force_open: bool,
+    pub access_mode: Option<AccessMode>,
+    pub lock_timeout: Option<Duration>,
+    pub track_access: bool,
+    pub collect_metrics: bool,
+    pub type_tag: Option<u32>,
+    pub autosave: bool,
+    pub dictionary: Option<Vec<u8>>,
 }
 
 impl StoreOptions {
+    #[cfg(feature = "std")]
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
-            compression_level: CompressionLevel::DEFAULT,
+            ..Self::default()
         }
     }
 
+    /// Creates options for a file named `file_name` in the platform's data directory for
+    /// `app_name`, such as `~/.local/share/<app_name>` on Linux or `%APPDATA%\<app_name>\data` on
+    /// Windows. The directory is created on the first `save` unless
+    /// [`create_dirs`](Self::set_create_dirs) is disabled. Returns `None` if no home directory
+    /// could be found.
+    #[cfg(feature = "std")]
+    pub fn in_data_dir(app_name: &str, file_name: &str) -> Option<Self> {
+        let dirs = directories::ProjectDirs::from("", "", app_name)?;
+        Some(Self::new(dirs.data_dir().join(file_name)))
+    }
+
     pub fn set_compression_level(&mut self, level: CompressionLevel) {
         self.compression_level = level;
     }
@@ -81,103 +202,1271 @@ impl StoreOptions {
         self.set_compression_level(level);
         self
     }
+
+    /// Sets the number of worker threads zstd uses when compressing on save.
+    /// A value of 0 or 1 compresses on the calling thread, which is the default.
+    /// Only payloads larger than zstd's job size (several MiB at most levels) are split across
+    /// workers, so small stores see no benefit. Files written this way open normally.
+    pub fn set_compression_threads(&mut self, threads: u32) {
+        self.compression_threads = threads;
+    }
+
+    pub fn with_compression_threads(mut self, threads: u32) -> Self {
+        self.set_compression_threads(threads);
+        self
+    }
+
+    /// Stores the payload uncompressed when it serializes to fewer than `bytes` bytes, which avoids
+    /// paying for compression that wouldn't help tiny stores. Whether a file is compressed is
+    /// recorded in its header, so it opens the same either way. The default of 0 always compresses.
+    /// Any other value requires buffering the serialized payload in memory while saving.
+    pub fn set_min_compress_bytes(&mut self, bytes: usize) {
+        self.min_compress_bytes = bytes;
+    }
+
+    pub fn with_min_compress_bytes(mut self, bytes: usize) -> Self {
+        self.set_min_compress_bytes(bytes);
+        self
+    }
+
+    /// When enabled, entries are saved in the order their keys were first inserted rather than in
+    /// the map's arbitrary order, giving deterministic files that diff cleanly in version control.
+    /// Only keys inserted with [`Store::insert`] have a position; the rest, such as entries loaded
+    /// from disk, are written first, ordered by their encoded keys. Tracking positions costs a
+    /// clone of every inserted key.
+    pub fn set_stable_order(&mut self, stable: bool) {
+        self.stable_order = stable;
+    }
+
+    pub fn with_stable_order(mut self, stable: bool) -> Self {
+        self.set_stable_order(stable);
+        self
+    }
+
+    /// When enabled, removing a key with [`Store::remove`] or [`Store::clear`] records a tombstone
+    /// holding the time of deletion, so that peers syncing from the saved file learn about the
+    /// deletion instead of the key silently vanishing. Tombstones are saved along with the entries
+    /// and are dropped when their key is inserted again or by [`Store::purge_tombstones`].
+    pub fn set_tombstones(&mut self, enabled: bool) {
+        self.tombstones = enabled;
+    }
+
+    pub fn with_tombstones(mut self, enabled: bool) -> Self {
+        self.set_tombstones(enabled);
+        self
+    }
+
+    /// Caps how many bytes a store's payload may decompress to while reading it, failing with
+    /// `Error::DecompressionLimitExceeded` past that, so that opening an untrusted file can't
+    /// exhaust memory. The layers of its delta file are held to the same cap, together.
+    /// The default of `None` applies no limit.
+    pub fn set_max_decompressed_bytes(&mut self, max: Option<u64>) {
+        self.max_decompressed_bytes = max;
+    }
+
+    pub fn with_max_decompressed_bytes(mut self, max: Option<u64>) -> Self {
+        self.set_max_decompressed_bytes(max);
+        self
+    }
+
+    /// When enabled, the store records which keys are inserted or removed so that
+    /// [`Store::save_incremental`] can write just those. Tracking changes costs a clone of every
+    /// inserted or removed key. Values handed out by [`Store::get_mut`] and similar methods are
+    /// tracked as changed too, but changes made through [`Store::map_mut`] must be reported with
+    /// [`Store::mark_changed`].
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+
+    pub fn with_incremental(mut self, incremental: bool) -> Self {
+        self.set_incremental(incremental);
+        self
+    }
+
+    /// Compacts the store automatically once its delta file grows larger than `ratio` times the
+    /// size of the snapshot it applies to, e.g. `Some(0.5)` for half. The check happens on
+    /// [`Store::open`], which then writes a new snapshot, and on [`Store::save_incremental`], which
+    /// does a full save instead of appending. The default of `None` never compacts automatically.
+    pub fn set_auto_compact_ratio(&mut self, ratio: Option<f32>) {
+        self.auto_compact_ratio = ratio;
+    }
+
+    pub fn with_auto_compact_ratio(mut self, ratio: Option<f32>) -> Self {
+        self.set_auto_compact_ratio(ratio);
+        self
+    }
+
+    /// When enabled, `save` writes a Blake3 checksum of every value alongside the entries, which
+    /// [`Store::verify_entry`] checks to detect records that were altered. This costs 32 bytes per
+    /// entry on disk and hashing every value on each save.
+    pub fn set_entry_checksums(&mut self, enabled: bool) {
+        self.entry_checksums = enabled;
+    }
+
+    pub fn with_entry_checksums(mut self, enabled: bool) -> Self {
+        self.set_entry_checksums(enabled);
+        self
+    }
+
+    /// When enabled, `save` sizes the new file up front to the size of the previous save before
+    /// writing it, and trims it to the actual size afterwards. On filesystems that allocate space
+    /// for the reserved length this avoids fragmentation and fails early when the disk is full,
+    /// rather than partway through the write. Nothing is reserved for the first save of a store.
+    pub fn set_preallocate(&mut self, enabled: bool) {
+        self.preallocate = enabled;
+    }
+
+    pub fn with_preallocate(mut self, enabled: bool) -> Self {
+        self.set_preallocate(enabled);
+        self
+    }
+
+    /// When enabled, reading a store whose file format version doesn't match this library's
+    /// attempts to decode it anyway instead of failing with `Error::FileTooNew` or
+    /// `Error::FileTooOld`, e.g. for recovery tools. Decoding may still fail, or produce wrong
+    /// results, if the formats differ. Saving always writes the current version.
+    pub fn set_force_open(&mut self, enabled: bool) {
+        self.force_open = enabled;
+    }
+
+    pub fn with_force_open(mut self, enabled: bool) -> Self {
+        self.set_force_open(enabled);
+        self
+    }
+
+    /// Coordinates with other processes through an advisory lock on a file next to the store,
+    /// named after it with a `.lock` suffix, e.g. `data.ogma.lock`. Readers hold a shared lock
+    /// while the store is open and a writer holds an exclusive lock while saving, so a save never
+    /// happens while a reader has the store open. A lock that can't be taken fails with
+    /// `Error::Locked`, after waiting for up to `lock_timeout`. Only processes that lock the
+    /// store are coordinated. The default of `None` takes no locks.
+    pub fn set_access_mode(&mut self, mode: Option<AccessMode>) {
+        self.access_mode = mode;
+    }
+
+    pub fn with_access_mode(mut self, mode: AccessMode) -> Self {
+        self.set_access_mode(Some(mode));
+        self
+    }
+
+    /// How long to wait for the lock of `access_mode` before failing with `Error::Locked`.
+    /// The default of `None` fails immediately.
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.set_lock_timeout(Some(timeout));
+        self
+    }
+
+    /// When enabled, the store remembers when each key was inserted and how often and when it
+    /// was last read through `Store::get_tracked`, as reported by `Store::entry_metadata`.
+    /// This costs a clone of every key inserted and some memory per entry.
+    pub fn set_track_access(&mut self, enabled: bool) {
+        self.track_access = enabled;
+    }
+
+    pub fn with_track_access(mut self, enabled: bool) -> Self {
+        self.set_track_access(enabled);
+        self
+    }
+
+    /// When enabled, the store times its opens, insertions, removals, and saves, as reported by
+    /// `Store::metrics`. Reads go through the underlying map and aren't timed.
+    pub fn set_collect_metrics(&mut self, enabled: bool) {
+        self.collect_metrics = enabled;
+    }
+
+    pub fn with_collect_metrics(mut self, enabled: bool) -> Self {
+        self.set_collect_metrics(enabled);
+        self
+    }
+
+    /// An application-chosen tag identifying the key and value types, saved in the file's header.
+    /// Opening a file whose tag differs fails with `Error::TypeMismatch` instead of decoding the
+    /// entries as the wrong types. Files without a tag, and any file when no tag is set, are
+    /// accepted as before. Change the tag whenever `K` or `V` changes incompatibly.
+    pub fn set_type_tag(&mut self, tag: Option<u32>) {
+        self.type_tag = tag;
+    }
+
+    pub fn with_type_tag(mut self, tag: Option<u32>) -> Self {
+        self.set_type_tag(tag);
+        self
+    }
+
+    /// A zstd dictionary to compress with, such as one trained by [`Store::train_dictionary`].
+    /// The payload is compressed as a single frame whose entries already share context, so this
+    /// pays off mostly for small stores of similar entries, e.g. many small files. Its hash is saved in
+    /// the file's header, and opening the file then requires the same dictionary, failing with
+    /// `Error::DictionaryRequired` without one or `Error::DictionaryMismatch` with another.
+    /// Setting a dictionary doesn't prevent opening files saved without one.
+    pub fn set_dictionary(&mut self, dictionary: Option<Vec<u8>>) {
+        self.dictionary = dictionary;
+    }
+
+    pub fn with_dictionary(mut self, dictionary: Option<Vec<u8>>) -> Self {
+        self.set_dictionary(dictionary);
+        self
+    }
+
+    /// When enabled, `save` refuses to replace a file that was modified by someone else since the
+    /// store was opened or last saved, failing with `Error::ConcurrentModification` instead.
+    /// Changes are detected by the file's size and modification time. The check happens just before
+    /// the new file is renamed into place, so it narrows the window for lost updates rather than
+    /// closing it entirely.
+    pub fn set_check_before_overwrite(&mut self, check: bool) {
+        self.check_before_overwrite = check;
+    }
+
+    pub fn with_check_before_overwrite(mut self, check: bool) -> Self {
+        self.set_check_before_overwrite(check);
+        self
+    }
+
+    /// When enabled, `save` creates any missing parent directories of the path before writing.
+    /// Enabled by default.
+    pub fn set_create_dirs(&mut self, create: bool) {
+        self.create_dirs = create;
+    }
+
+    pub fn with_create_dirs(mut self, create: bool) -> Self {
+        self.set_create_dirs(create);
+        self
+    }
+
+    /// Sets how many previous versions of the file `save` keeps, as `path.bak.1` (the most recent)
+    /// through `path.bak.N`. The default of 0 keeps no backups.
+    pub fn set_backups(&mut self, count: usize) {
+        self.backups = count;
+    }
+
+    pub fn with_backups(mut self, count: usize) -> Self {
+        self.set_backups(count);
+        self
+    }
+
+    /// When enabled, [`Store::try_insert`], [`Store::try_remove`], and [`Store::try_clear`] write
+    /// each change through to disk before returning, so the file is readable and up to date even
+    /// if the store is never saved explicitly. Other changes are held until [`Store::flush`] or a
+    /// save, or until the store is dropped, which flushes it but can't report a failed write.
+    /// Only a store that was opened with [`Store::open`] or saved since it was created flushes
+    /// when dropped, and of a store and its clones, only the original does.
+    pub fn set_autosave(&mut self, enabled: bool) {
+        self.autosave = enabled;
+    }
+
+    pub fn with_autosave(mut self, enabled: bool) -> Self {
+        self.set_autosave(enabled);
+        self
+    }
+
+    /// Turns the delta file into a write-ahead log by enabling or disabling both
+    /// [`autosave`](Self::set_autosave) and [`incremental`](Self::set_incremental). Each
+    /// `try_insert`, `try_remove`, and `try_clear` then appends its change to the delta file and
+    /// syncs it before returning, and [`Store::open`] replays the log on top of the last full
+    /// save, which checkpoints by removing it. A record torn by a crash fails its checksum and is
+    /// dropped. To log a batch of changes as one record, make them with `insert` and `remove`
+    /// and then call [`Store::flush`].
+    pub fn set_wal(&mut self, enabled: bool) {
+        self.set_autosave(enabled);
+        self.set_incremental(enabled);
+    }
+
+    pub fn with_wal(mut self, enabled: bool) -> Self {
+        self.set_wal(enabled);
+        self
+    }
 }
 
 impl Default for StoreOptions {
+    /// Options without a path, for a store that only lives in memory or is encoded with
+    /// `to_bytes`. Saving or opening such a store fails with `Error::InvalidPath`.
     fn default() -> Self {
-        Self::new("")
+        Self {
+            #[cfg(feature = "std")]
+            path: PathBuf::new(),
+            compression_level: CompressionLevel::DEFAULT,
+            compression_threads: 1,
+            check_before_overwrite: false,
+            backups: 0,
+            min_compress_bytes: 0,
+            stable_order: false,
+            tombstones: false,
+            create_dirs: true,
+            incremental: false,
+            max_decompressed_bytes: None,
+            auto_compact_ratio: None,
+            entry_checksums: false,
+            preallocate: false,
+            force_open: false,
+            access_mode: None,
+            lock_timeout: None,
+            track_access: false,
+            collect_metrics: false,
+            type_tag: None,
+            autosave: false,
+            dictionary: None,
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Store<K, V>
+/// The hasher a store's map uses unless another is chosen, as for [`HashMap`].
+/// It's the same with and without the `std` feature.
+pub type DefaultHashBuilder = hashbrown::DefaultHashBuilder;
+
+/// A map of keys to values that can be saved to and opened from disk.
+/// Its map hashes keys with `S`, which isn't part of the file format, so a store saved with one
+/// hasher opens with any other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize, V: Serialize",
+    deserialize = "K: Deserialize<'de>, V: Deserialize<'de>, S: Default"
+))]
+pub struct Store<K, V, S = DefaultHashBuilder>
 where
     K: Eq + Hash,
+    S: BuildHasher,
 {
-    map: HashMap<K, V>,
+    pub(crate) map: HashMap<K, V, S>,
+
+    #[serde(skip)]
+    pub(crate) options: StoreOptions,
+
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "Option::default")]
+    events: Option<Sender<StoreEvent<K>>>,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    hook: Hook<K, V>,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) disk_stamp: DiskStamp,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) delta_end: DeltaEnd,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) drop_flush: DropFlush<K, V, S>,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) revision: Revision,
+
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "InsertionOrder::new")]
+    pub(crate) insertion_order: InsertionOrder<K>,
+
+    /// Deleted keys and when they were deleted, in milliseconds since the UNIX epoch.
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "HashMap::new")]
+    pub(crate) tombstones: HashMap<K, u64>,
+
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "ChangeSet::new")]
+    pub(crate) changes: ChangeSet<K>,
+
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) metadata: Option<Vec<u8>>,
+
+    /// The last id issued by `next_id`.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) sequence: u64,
+
+    /// Access metadata of entries, for `StoreOptions::track_access`.
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "HashMap::new")]
+    pub(crate) access: HashMap<K, EntryMeta>,
 
+    #[cfg(feature = "std")]
     #[serde(skip)]
-    options: StoreOptions,
+    pub(crate) metrics: Metrics,
+
+    /// The shared lock held for `AccessMode::Read`.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) lock: FileLock,
+
+    /// The checksum of every value as read from disk, for `StoreOptions::entry_checksums`.
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "HashMap::new")]
+    pub(crate) checksums: HashMap<K, Digest>,
+
+    /// When entries inserted with a TTL expire, in milliseconds since the UNIX epoch.
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "HashMap::new")]
+    pub(crate) expiries: HashMap<K, u64>,
+
+    /// The secondary indexes registered with `add_index`.
+    #[cfg(feature = "std")]
+    #[serde(skip, default = "Indexes::new")]
+    pub(crate) indexes: Indexes<K, V>,
 }
 
-impl<K, V> Deref for Store<K, V>
+impl<K, V, S> Deref for Store<K, V, S>
 where
     K: Eq + Hash,
+    S: BuildHasher + Default,
 {
-    type Target = HashMap<K, V>;
+    type Target = HashMap<K, V, S>;
 
     fn deref(&self) -> &Self::Target {
         &self.map
     }
 }
 
-impl<K, V> DerefMut for Store<K, V>
+impl<K, V, S> From<HashMap<K, V, S>> for Store<K, V, S>
 where
     K: Eq + Hash,
+    S: BuildHasher + Default,
 {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.map
+    /// Wraps an existing map in a store with default options.
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let mut store = Self::new(StoreOptions::default());
+        store.map = map;
+        store
     }
 }
 
-impl<K, V> Store<K, V>
+impl<K, V, S> From<Store<K, V, S>> for HashMap<K, V, S>
 where
-    K: Eq + Hash + Serialize + for<'de> Deserialize<'de>,
-    V: Serialize + for<'de> Deserialize<'de>,
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Unwraps the store's map, discarding its options.
+    fn from(store: Store<K, V, S>) -> Self {
+        store.into_map()
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
 {
     pub fn new(options: StoreOptions) -> Self {
+        Self::with_capacity(options, 0)
+    }
+
+    /// Creates an empty store with room for at least `capacity` entries before its map has to
+    /// grow, e.g. before loading a known number of entries from elsewhere.
+    pub fn with_capacity(options: StoreOptions, capacity: usize) -> Self {
         Self {
-            map: HashMap::new(),
+            map: HashMap::with_capacity_and_hasher(capacity, S::default()),
             options,
+            #[cfg(feature = "std")]
+            events: None,
+            #[cfg(feature = "std")]
+            hook: Hook::default(),
+            #[cfg(feature = "std")]
+            disk_stamp: DiskStamp::default(),
+            #[cfg(feature = "std")]
+            delta_end: DeltaEnd::default(),
+            #[cfg(feature = "std")]
+            drop_flush: DropFlush::default(),
+            #[cfg(feature = "std")]
+            revision: Revision::default(),
+            #[cfg(feature = "std")]
+            insertion_order: InsertionOrder::new(),
+            #[cfg(feature = "std")]
+            tombstones: HashMap::new(),
+            #[cfg(feature = "std")]
+            changes: ChangeSet::new(),
+            #[cfg(feature = "std")]
+            metadata: None,
+            #[cfg(feature = "std")]
+            sequence: 0,
+            #[cfg(feature = "std")]
+            access: HashMap::new(),
+            #[cfg(feature = "std")]
+            metrics: Metrics::default(),
+            #[cfg(feature = "std")]
+            lock: FileLock::default(),
+            #[cfg(feature = "std")]
+            checksums: HashMap::new(),
+            #[cfg(feature = "std")]
+            expiries: HashMap::new(),
+            #[cfg(feature = "std")]
+            indexes: Indexes::new(),
         }
     }
 
-    pub fn open(options: StoreOptions) -> Result<Self> {
-        if !options.path.exists() || !options.path.is_file() {
-            Ok(Self {
-                map: HashMap::new(),
-                options,
-            })
-        } else {
-            let mut file = File::open(&options.path)?;
-            let mut magic_id = [0u8; 4];
-            file.read_exact(&mut magic_id)?;
-            if magic_id != MAGIC_ID {
-                return Err(Error::InvalidFile);
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+
+    /// Whether the store was changed since it was opened or last saved.
+    /// Any mutable access to the entries counts as a change, even if nothing was modified,
+    /// as do changes that were later undone; use
+    /// [`differs_from_disk`](Self::differs_from_disk) to compare the contents.
+    #[cfg(feature = "std")]
+    pub fn needs_save(&self) -> bool {
+        !self.revision.is_saved()
+    }
+
+    /// The application metadata saved with the store, if any.
+    #[cfg(feature = "std")]
+    pub fn metadata(&self) -> Option<&[u8]> {
+        self.metadata.as_deref()
+    }
+
+    /// Sets arbitrary application metadata, such as schema notes, to save in the file alongside
+    /// the entries. It is stored uncompressed right after the header and read back by `open`.
+    /// Delta layers don't hold metadata, so with `StoreOptions::incremental` the next save is full.
+    #[cfg(feature = "std")]
+    pub fn set_metadata(&mut self, metadata: Option<Vec<u8>>) {
+        self.revision.bump();
+        if self.options.incremental {
+            self.changes.rewrite();
+        }
+        self.metadata = metadata;
+    }
+
+    /// Issues the next id of a sequence kept in the file's header, starting at 1, e.g. for
+    /// auto-incrementing keys. Ids are never issued twice across restarts as long as the store is
+    /// saved, with `save` or `save_incremental`, before it is dropped; ids issued since the last
+    /// save are issued again after reopening.
+    #[cfg(feature = "std")]
+    pub fn next_id(&mut self) -> u64 {
+        self.sequence += 1;
+        self.revision.bump();
+        if self.options.incremental {
+            self.changes.record_sequence();
+        }
+        self.sequence
+    }
+
+    /// The last id issued by [`next_id`](Self::next_id), or 0 if none was.
+    #[cfg(feature = "std")]
+    pub fn last_id(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Consumes the store, returning an iterator over its keys.
+    pub fn into_keys(self) -> impl Iterator<Item = K> {
+        self.into_map().into_keys()
+    }
+
+    /// Consumes the store, returning an iterator over its values.
+    pub fn into_values(self) -> impl Iterator<Item = V> {
+        self.into_map().into_values()
+    }
+
+    /// Takes the map out of a store that is being consumed. Dropping what is left of the store
+    /// doesn't flush it, since that would write an empty store over the file.
+    pub(crate) fn into_map(mut self) -> HashMap<K, V, S> {
+        #[cfg(feature = "std")]
+        self.drop_flush.set(None);
+        core::mem::take(&mut self.map)
+    }
+
+    /// Consumes the store, building a new one with default options from the result of calling `f`
+    /// on every entry. If `f` maps several entries to the same key, only one of them is kept.
+    pub fn map_into<K2, V2, F>(self, mut f: F) -> Store<K2, V2>
+    where
+        K2: Eq + Hash,
+        F: FnMut(K, V) -> (K2, V2),
+    {
+        let mut store = Store::new(StoreOptions::default());
+        store.map = self
+            .into_map()
+            .into_iter()
+            .map(|(key, value)| f(key, value))
+            .collect();
+        store
+    }
+
+    /// Consumes the store, building a new one with the same keys and options from the result of
+    /// calling `f` on every value, e.g. to migrate the values to a new type in place. Saving the
+    /// new store replaces the file at `options.path`, even with `StoreOptions::incremental`, since
+    /// the delta file holds values of the old type.
+    ///
+    /// Everything but the values carries over, including the metadata, the id sequence,
+    /// tombstones, expiries, and insertion order. The exceptions are the entry checksums, which
+    /// are recomputed by the next save, and the indexes and change hook, which take values of the
+    /// old type. With `StoreOptions::autosave`, the new store is only flushed when it's dropped
+    /// once it has been saved.
+    pub fn map_values<V2, F>(mut self, mut f: F) -> Store<K, V2, S>
+    where
+        F: FnMut(V) -> V2,
+    {
+        use core::mem::take;
+
+        let options = take(&mut self.options);
+        let map = self
+            .map
+            .drain()
+            .map(|(key, value)| (key, f(value)))
+            .collect();
+
+        #[cfg(feature = "std")]
+        let mut store = Store {
+            map,
+            options,
+            events: self.events.take(),
+            hook: Hook::default(),
+            disk_stamp: take(&mut self.disk_stamp),
+            delta_end: take(&mut self.delta_end),
+            drop_flush: DropFlush::default(),
+            revision: take(&mut self.revision),
+            insertion_order: core::mem::replace(&mut self.insertion_order, InsertionOrder::new()),
+            tombstones: take(&mut self.tombstones),
+            changes: core::mem::replace(&mut self.changes, ChangeSet::new()),
+            metadata: self.metadata.take(),
+            sequence: self.sequence,
+            access: take(&mut self.access),
+            metrics: take(&mut self.metrics),
+            lock: take(&mut self.lock),
+            checksums: HashMap::new(),
+            expiries: take(&mut self.expiries),
+            indexes: Indexes::new(),
+        };
+        #[cfg(not(feature = "std"))]
+        let store = Store { map, options };
+
+        // What is left of this store is empty, so it must not be flushed when it's dropped.
+        #[cfg(feature = "std")]
+        self.drop_flush.set(None);
+
+        #[cfg(feature = "std")]
+        {
+            store.revision.bump();
+            store.changes.rewrite();
+        }
+        store
+    }
+
+    /// Looks up each of `keys` lazily, yielding the value for each key in order, or `None` if it
+    /// isn't present.
+    pub fn get_iter<'a, I>(&'a self, keys: I) -> impl Iterator<Item = Option<&'a V>>
+    where
+        I: IntoIterator<Item = &'a K>,
+    {
+        keys.into_iter().map(|key| self.map.get(key))
+    }
+
+    /// Looks up every key of `keys`, returning the value for each key in order, or `None` if it
+    /// isn't present.
+    pub fn get_many(&self, keys: &[&K]) -> Vec<Option<&V>> {
+        keys.iter().map(|key| self.map.get(*key)).collect()
+    }
+
+    /// Returns a value for every key in `range`, in order, substituting `V::default()` for keys
+    /// that aren't present. This views a dense region of integer keys like an array.
+    pub fn get_range_or_default(&self, range: Range<K>) -> Vec<V>
+    where
+        Range<K>: Iterator<Item = K>,
+        V: Default + Clone,
+    {
+        range
+            .map(|key| self.map.get(&key).cloned().unwrap_or_default())
+            .collect()
+    }
+
+    /// Returns up to `limit` entries starting at `offset`, in key order, so that each page is
+    /// consistent across calls while the store is unchanged.
+    /// Every call sorts all of the keys, which is fine for moderately sized stores; to page through
+    /// huge stores often, keep the keys in a sorted structure such as a `BTreeSet` instead.
+    pub fn page(&self, offset: usize, limit: usize) -> Vec<(&K, &V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self.map.iter().collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        entries.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Calls `f` on every entry with mutable access to its value, stopping at and returning the
+    /// first error. Entries visited before the error keep any changes made to them.
+    /// With `StoreOptions::incremental`, the next save is full, since any value may have changed.
+    pub fn try_for_each_mut<E, F>(&mut self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(&K, &mut V) -> Result<(), E>,
+    {
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            if self.options.incremental {
+                self.changes.rewrite();
+            }
+            self.indexes.invalidate_all();
+        }
+        self.map
+            .iter_mut()
+            .try_for_each(|(key, value)| f(key, value))
+    }
+
+    /// Returns an iterator over every entry, in unspecified order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
+    /// Returns an iterator over every entry with mutable access to its value, in unspecified order.
+    /// Like [`try_for_each_mut`](Self::try_for_each_mut), this counts as a change to every entry,
+    /// so with `StoreOptions::incremental` the next save is full.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            if self.options.incremental {
+                self.changes.rewrite();
+            }
+            self.indexes.invalidate_all();
+        }
+        self.map.iter_mut()
+    }
+
+    /// Mutable access to the underlying map, for operations the store doesn't provide itself.
+    /// This counts as a change to the store, but the store can't see what is changed through it:
+    /// no events are sent, no tombstones are recorded, expiries aren't updated, indexes are
+    /// rebuilt at the next change, and with `StoreOptions::incremental` the next save is full.
+    pub fn map_mut(&mut self) -> &mut HashMap<K, V, S> {
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            if self.options.incremental {
+                self.changes.rewrite();
+            }
+            self.indexes.invalidate_all();
+        }
+        &mut self.map
+    }
+
+    /// Reserves room for at least `additional` more entries, like [`HashMap::reserve`]. Unlike
+    /// reserving through [`map_mut`](Self::map_mut), this doesn't count as a change.
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    /// Shrinks the capacity of the store's maps as much as possible, e.g. after removing most
+    /// entries. Unlike shrinking through [`map_mut`](Self::map_mut), this doesn't count as a change, and it
+    /// also releases the memory of the tombstones and other per-key bookkeeping.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Shrinks the capacity of the store's maps to at least `min_capacity` entries, like
+    /// [`shrink_to_fit`](Self::shrink_to_fit) but keeping room to grow.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.map.shrink_to(min_capacity);
+        #[cfg(feature = "std")]
+        {
+            self.tombstones.shrink_to(min_capacity);
+            self.checksums.shrink_to(min_capacity);
+            self.access.shrink_to(min_capacity);
+            self.expiries.shrink_to(min_capacity);
+            self.insertion_order.shrink_to(min_capacity);
+        }
+    }
+}
+
+impl<K, V, S> IntoIterator for Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Item = (K, V);
+    type IntoIter = <HashMap<K, V, S> as IntoIterator>::IntoIter;
+
+    /// Consumes the store, returning an iterator over its entries in unspecified order.
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_map().into_iter()
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Builds a store with default options, like
+    /// [`from_iter_with_options`](Store::from_iter_with_options).
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self::from_iter_with_options(iter, StoreOptions::default())
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Inserts every entry of `iter` with [`insert`](Store::insert), reserving room for as many
+    /// entries as `iter` is sure to yield first.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = <&'a HashMap<K, V, S> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    #[cfg(feature = "std")]
+    /// Registers a channel that receives a [`StoreEvent`] for every change made through
+    /// [`insert`](Self::insert), [`remove`](Self::remove), and [`clear`](Self::clear).
+    /// Each event is sent within the call that made the change, after the map is updated, so
+    /// events arrive in the order the changes were made.
+    /// Sending is best-effort: a disconnected receiver is ignored and never fails the mutation.
+    /// The sender is never saved, and a store opened from disk starts without one.
+    /// Values modified in place (e.g. through `get_mut` or `entry`) and changes made through
+    /// [`map_mut`](Self::map_mut) are not reported.
+    pub fn set_event_sender(&mut self, sender: Option<Sender<StoreEvent<K>>>) {
+        self.events = sender;
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_event_sender(mut self, sender: Sender<StoreEvent<K>>) -> Self {
+        self.set_event_sender(Some(sender));
+        self
+    }
+
+    #[cfg(feature = "std")]
+    /// Registers a callback that is passed a [`ChangeEvent`] with the values involved in every
+    /// change made through [`insert`](Self::insert), [`remove`](Self::remove),
+    /// [`retain`](Self::retain), and [`clear`](Self::clear), replacing any previous one.
+    /// Like the events of [`set_event_sender`](Self::set_event_sender), it is called within the
+    /// call that made the change, after the map is updated, and only for the same changes.
+    /// The hook is never saved, and a store opened from disk starts without one. Clones of the
+    /// store share the hook, and a store converted with [`map_values`](Self::map_values) drops it.
+    pub fn set_change_hook(&mut self, hook: Option<ChangeHook<K, V>>) {
+        self.hook.set(hook);
+    }
+
+    /// Builds a store with `options` from the entries of `iter`, as if each were inserted in turn.
+    /// If the same key occurs several times, the last value is kept. Room is reserved up front for
+    /// as many entries as `iter` is sure to yield, according to its size hint.
+    pub fn from_iter_with_options<I>(iter: I, options: StoreOptions) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut store = Self::new(options);
+        store.extend(iter);
+        store
+    }
+
+    /// Inserts a key-value pair, returning the previous value if the key was present and hadn't
+    /// expired.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        #[cfg(feature = "std")]
+        self.refresh_indexes();
+        #[cfg(feature = "std")]
+        let _timer = self
+            .metrics
+            .time(self.options.collect_metrics, Operation::Insert);
+
+        #[cfg(feature = "std")]
+        if self.options.stable_order {
+            self.insertion_order.record(&key);
+        }
+
+        #[cfg(feature = "std")]
+        let expired = self.is_expired(&key);
+        #[cfg(feature = "std")]
+        {
+            self.tombstones.remove(&key);
+            self.expiries.remove(&key);
+            self.revision.bump();
+        }
+
+        #[cfg(feature = "std")]
+        if self.options.incremental {
+            self.changes.record(key.clone());
+        }
+
+        #[cfg(feature = "std")]
+        if self.options.track_access {
+            self.access
+                .entry(key.clone())
+                .or_insert_with(EntryMeta::inserted_now);
+        }
+
+        #[cfg(feature = "std")]
+        let observed_key =
+            (self.events.is_some() || self.hook.is_set() || !self.indexes.is_empty())
+                .then(|| key.clone());
+        let old = self.map.insert(key, value);
+
+        #[cfg(feature = "std")]
+        let old = {
+            if let Some(key) = &observed_key {
+                self.indexes.replace(key, old.as_ref(), &self.map[key]);
+            }
+
+            // An expired value was already absent as far as the caller could tell.
+            let old = old.filter(|_| !expired);
+            if let Some(key) = observed_key {
+                self.hook.call(&ChangeEvent::Inserted {
+                    key: &key,
+                    old: old.as_ref(),
+                    new: &self.map[&key],
+                });
+                self.emit(match old {
+                    Some(_) => StoreEvent::Updated(key),
+                    None => StoreEvent::Inserted(key),
+                });
+            }
+            old
+        };
+
+        old
+    }
+
+    /// Moves every entry of `other` into this store with [`insert`](Self::insert), passing keys
+    /// present in both to `resolve` along with the current value and `other`'s value, and storing
+    /// what it returns. Nothing is written to disk until the store is saved.
+    /// Use [`merge`](Self::merge) when one side should simply win.
+    pub fn merge_with<F>(&mut self, other: Self, mut resolve: F)
+    where
+        F: FnMut(&K, &V, V) -> V,
+    {
+        for (key, value) in other.into_map() {
+            let value = match self.get(&key) {
+                Some(current) => resolve(&key, current, value),
+                None => value,
+            };
+            self.insert(key, value);
+        }
+    }
+
+    /// Adds `delta` to the value of `key`, treating a missing or expired value as `V::default()`,
+    /// and returns the new value. The update goes through [`insert`](Self::insert), so it is
+    /// reported and tracked like any other insertion.
+    pub fn increment(&mut self, key: &K, delta: V) -> V
+    where
+        V: Add<Output = V> + Copy + Default,
+    {
+        let value = self.get(key).copied().unwrap_or_default() + delta;
+        self.insert(key.clone(), value);
+        value
+    }
+
+    /// Returns a mutable reference to the value of `key`, or `None` if it isn't present.
+    /// The key is tracked as changed like with [`get_mut_or_default`](Self::get_mut_or_default).
+    /// An expired entry is removed with [`remove`](Self::remove) instead.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        #[cfg(feature = "std")]
+        self.remove_expired(key);
+        let key = self.map.get_key_value(key)?.0.clone();
+        self.touch(&key);
+        self.map.get_mut(&key)
+    }
+
+    /// Returns a mutable reference to the value of `key`, inserting `V::default()` first if it
+    /// isn't present, e.g. `*store.get_mut_or_default(key) += 1`.
+    /// A missing key is inserted through [`insert`](Self::insert), and the key is tracked as
+    /// changed either way, since the value is assumed to be modified through the reference.
+    /// An expired entry is removed with [`remove`](Self::remove) and replaced like a missing one.
+    pub fn get_mut_or_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        #[cfg(feature = "std")]
+        self.remove_expired(&key);
+        if !self.map.contains_key(&key) {
+            self.insert(key.clone(), V::default());
+        }
+
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            self.mark_changed(key.clone());
+            self.indexes.invalidate(&key, &self.map[&key]);
+        }
+        self.map.get_mut(&key).expect("the key was just inserted")
+    }
+
+    /// Returns mutable references to the values of every key of `keys`, in order, with `None` for
+    /// keys that aren't present, or `None` altogether if a key is requested more than once, since
+    /// it can't be borrowed mutably twice. Present keys are tracked as changed like with
+    /// [`get_mut_or_default`](Self::get_mut_or_default), and expired entries are removed with
+    /// [`remove`](Self::remove) like with [`get_mut`](Self::get_mut).
+    pub fn get_many_mut(&mut self, keys: &[&K]) -> Option<Vec<Option<&mut V>>> {
+        let mut seen = HashSet::with_capacity(keys.len());
+        if !keys.iter().all(|&key| seen.insert(key)) {
+            return None;
+        }
+        for &key in keys {
+            #[cfg(feature = "std")]
+            self.remove_expired(key);
+            if self.map.contains_key(key) {
+                self.touch(key);
+            }
+        }
+
+        // Everything else is done with the store before the first pointer is taken, so that only
+        // the map is borrowed from then on.
+        let map = &mut self.map;
+        let values: Vec<Option<*mut V>> = keys
+            .iter()
+            .map(|&key| map.get_mut(key).map(|value| value as *mut V))
+            .collect();
+
+        // SAFETY: the keys are distinct, so every pointer refers to the value of a different entry,
+        // nothing but `get_mut` on the map runs while the pointers are collected, and the map can't
+        // be modified while the references are alive since they borrow `self`.
+        Some(
+            values
+                .into_iter()
+                .map(|value| value.map(|value| unsafe { &mut *value }))
+                .collect(),
+        )
+    }
+
+    /// Returns the entry of `key` for in-place manipulation, like [`HashMap::entry`], e.g.
+    /// `store.entry(id).or_insert_with(Vec::new).push(x)`. Unlike the underlying map's entries,
+    /// missing keys are inserted through [`insert`](Self::insert), and a key whose value is handed
+    /// out or modified is tracked as changed like with
+    /// [`get_mut_or_default`](Self::get_mut_or_default). An expired entry is removed with
+    /// [`remove`](Self::remove) first, so it's treated as missing.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        #[cfg(feature = "std")]
+        self.remove_expired(&key);
+        Entry { store: self, key }
+    }
+
+    /// Records that the value of `key` may be modified through a mutable reference.
+    fn touch(&mut self, _key: &K) {
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            if self.options.incremental {
+                self.mark_changed(_key.clone());
+            }
+            if let Some(value) = self.map.get(_key) {
+                self.indexes.invalidate(_key, value);
             }
+        }
+    }
+
+    /// Removes a key, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        #[cfg(feature = "std")]
+        self.refresh_indexes();
+        let (_key, value) = {
+            #[cfg(feature = "std")]
+            let _timer = self
+                .metrics
+                .time(self.options.collect_metrics, Operation::Remove);
+            self.map.remove_entry(key)?
+        };
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            self.indexes.remove(&_key, &value);
+            self.hook.call(&ChangeEvent::Removed {
+                key: &_key,
+                value: &value,
+            });
+            self.removed(_key);
+        }
+
+        Some(value)
+    }
+
+    /// Updates the bookkeeping of `key` after its entry was removed from the map.
+    #[cfg(feature = "std")]
+    fn removed(&mut self, key: K) {
+        self.insertion_order.forget::<K>(&key);
+        self.access.remove::<K>(&key);
+        self.expiries.remove::<K>(&key);
+        if self.options.tombstones {
+            self.tombstones.insert(key.clone(), crate::tombstone::now());
+        }
+        if self.options.incremental {
+            self.changes.record(key.clone());
+        }
+        self.emit(StoreEvent::Removed(key));
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, like [`HashMap::retain`], removing the
+    /// rest as [`remove`](Self::remove) would. With `StoreOptions::incremental` enabled, the
+    /// entries that are kept are tracked as changed too, since `f` may modify their values.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        #[cfg(not(feature = "std"))]
+        self.map.retain(|key, value| f(key, value));
 
-            let version = file.read_u16::<LittleEndian>()?;
-            if version != VERSION {
-                return Err(Error::WrongVersion {
-                    expected: VERSION,
-                    actual: version,
+        #[cfg(feature = "std")]
+        {
+            self.refresh_indexes();
+            let removed: Vec<(K, V)> = self
+                .map
+                .extract_if(|key, value| {
+                    // `f` may modify the value, so it's indexed again if it's kept.
+                    self.indexes.remove(key, value);
+                    let keep = f(key, value);
+                    if keep {
+                        self.indexes.insert(key, value);
+                        if self.options.incremental {
+                            self.changes.record(key.clone());
+                        }
+                    }
+                    !keep
+                })
+                .collect();
+
+            self.revision.bump();
+            for (key, value) in removed {
+                self.hook.call(&ChangeEvent::Removed {
+                    key: &key,
+                    value: &value,
                 });
+                self.removed(key);
             }
+        }
+    }
 
-            let mut dec = Decoder::new(file)?;
-            let store: Store<K, V> = rmp_serde::decode::from_read(&mut dec)?;
+    /// Removes every key in `keys` with [`remove`](Self::remove), returning how many were present.
+    pub fn remove_many<'a, I>(&mut self, keys: I) -> usize
+    where
+        K: 'a,
+        I: IntoIterator<Item = &'a K>,
+    {
+        keys.into_iter()
+            .filter(|key| self.remove(*key).is_some())
+            .count()
+    }
 
-            Ok(Self { options, ..store })
+    /// Removes every entry.
+    pub fn clear(&mut self) {
+        #[cfg(feature = "std")]
+        if self.options.incremental {
+            // Tombstones are saved per key, so with them enabled every key has to be written.
+            match self.options.tombstones {
+                true => self
+                    .map
+                    .keys()
+                    .for_each(|key| self.changes.record(key.clone())),
+                false => self.changes.clear(),
+            }
+        }
+
+        #[cfg(feature = "std")]
+        if self.options.tombstones {
+            let now = crate::tombstone::now();
+            self.tombstones
+                .extend(self.map.drain().map(|(key, _)| (key, now)));
+        }
+
+        self.map.clear();
+        #[cfg(feature = "std")]
+        {
+            self.revision.bump();
+            self.insertion_order.clear();
+            self.access.clear();
+            self.expiries.clear();
+            self.indexes.clear();
+            self.hook.call(&ChangeEvent::Cleared);
+            self.emit(StoreEvent::Cleared);
         }
     }
 
-    pub fn save(&self) -> Result<()> {
-        let temp_path = self.options.path.with_extension("ogma.tmp");
-        let mut file = File::create(&temp_path)?;
+    #[cfg(feature = "std")]
+    fn emit(&self, event: StoreEvent<K>) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+}
 
-        file.write_all(MAGIC_ID)?;
-        file.write_u16::<LittleEndian>(VERSION)?;
+/// A key of a [`Store`] that may or may not be present, returned by [`Store::entry`].
+#[derive(Debug)]
+pub struct Entry<'a, K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    store: &'a mut Store<K, V, S>,
+    key: K,
+}
 
-        let mut enc = Encoder::new(file, self.options.compression_level.0)?;
-        rmp_serde::encode::write(&mut enc, self)?;
-        let mut file = enc.finish()?;
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
 
-        file.sync_all()?;
-        file.flush()?;
-        drop(file);
+    /// Returns the value of the key, inserting `default` first if it isn't present.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the value of the key, inserting the result of `f` first if it isn't present.
+    pub fn or_insert_with<F>(self, f: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let Self { store, key } = self;
+        if store.map.contains_key(&key) {
+            store.touch(&key);
+        } else {
+            store.insert(key.clone(), f());
+        }
+
+        store.map.get_mut(&key).expect("the key is present")
+    }
 
-        std::fs::rename(&temp_path, &self.options.path)?;
+    /// Returns the value of the key, inserting `V::default()` first if it isn't present.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
 
-        Ok(())
+    /// Calls `f` on the value of the key if it is present.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if self.store.map.contains_key(&self.key) {
+            self.store.touch(&self.key);
+            f(self
+                .store
+                .map
+                .get_mut(&self.key)
+                .expect("the key is present"));
+        }
+
+        self
     }
 }
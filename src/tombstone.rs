@@ -0,0 +1,51 @@
+use crate::store::Store;
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The current time in milliseconds since the UNIX epoch, as stored in tombstones.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Iterates over every tombstoned key along with the time it was deleted.
+    pub fn tombstones(&self) -> impl Iterator<Item = (&K, SystemTime)> {
+        self.tombstones
+            .iter()
+            .map(|(key, &millis)| (key, UNIX_EPOCH + Duration::from_millis(millis)))
+    }
+
+    /// Whether `key` was deleted while tombstones were enabled and hasn't been inserted since.
+    pub fn is_tombstoned<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tombstones.contains_key(key)
+    }
+
+    /// Removes tombstones for keys deleted more than `older_than` ago, returning how many were removed.
+    /// Once purged, peers that haven't synced yet will no longer learn about those deletions.
+    /// Delta layers can only add tombstones, so with `StoreOptions::incremental` the next save
+    /// after a purge is full.
+    pub fn purge_tombstones(&mut self, older_than: Duration) -> usize {
+        let cutoff = now().saturating_sub(older_than.as_millis() as u64);
+        let before = self.tombstones.len();
+        self.tombstones.retain(|_, &mut deleted| deleted > cutoff);
+        let purged = before - self.tombstones.len();
+        if purged > 0 {
+            self.revision.bump();
+            if self.options.incremental {
+                self.changes.rewrite();
+            }
+        }
+        purged
+    }
+}
@@ -0,0 +1,82 @@
+use crate::delta;
+use crate::error::Result;
+use crate::persist::FileStamp;
+use crate::store::{Store, StoreOptions};
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, Hash};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A handle to a background thread started by [`Store::watch`].
+/// Dropping it stops the thread.
+#[derive(Debug)]
+pub struct Watcher {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread immediately instead of after its next poll.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<K, V, S> Store<K, V, S>
+where
+    K: Eq + Hash + Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    V: Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    S: BuildHasher + Default,
+{
+    /// Watches the file at `options.path` and its delta file from a background thread, reopening
+    /// the store and passing it to `on_change` whenever either changes.
+    /// The files are checked every `poll_interval`, and a change is only reported once they have
+    /// stayed the same for a full interval, so a save that touches them several times is reported
+    /// once. Saves made through any store are reported, including those from this process.
+    /// Errors opening the changed store are passed to `on_change` rather than stopping the watch.
+    pub fn watch<F>(
+        options: StoreOptions,
+        poll_interval: Duration,
+        mut on_change: F,
+    ) -> Result<Watcher>
+    where
+        F: FnMut(Result<Self>) + Send + 'static,
+    {
+        let mut last = stamps(&options.path)?;
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut changed = false;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(poll_interval) {
+                let Ok(current) = stamps(&options.path) else {
+                    continue;
+                };
+
+                if current != last {
+                    last = current;
+                    changed = true;
+                } else if changed {
+                    changed = false;
+                    on_change(Self::open(options.clone()));
+                }
+            }
+        });
+
+        Ok(Watcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// The stamps of a store's file and its delta file.
+fn stamps(path: &Path) -> std::io::Result<(Option<FileStamp>, Option<FileStamp>)> {
+    Ok((
+        FileStamp::of_path(path)?,
+        FileStamp::of_path(&delta::delta_path(path))?,
+    ))
+}